@@ -1,15 +1,57 @@
-use anyhow::Result;
+use crate::manifest::MANIFEST_FILE;
+use anyhow::{Context, Result};
+use dcspkg::Package;
+use ed25519_dalek::{Signer, SigningKey};
 use flate2::write::GzEncoder;
 use flate2::{Compression, CrcWriter};
 use std::fs::File;
 use std::path::Path;
 
 //returns crc
+//the package manifest is only used to create the package, so is left out
 pub fn make_archive(install_path: &Path, dir_path: &Path) -> Result<u32> {
     let archive = File::create(install_path)?;
     let encoder = GzEncoder::new(archive, Compression::default());
     let encoder = CrcWriter::new(encoder);
     let mut tar = tar::Builder::new(encoder);
-    tar.append_dir_all(".", dir_path)?;
+    tar.append_dir(".", dir_path)?;
+    for entry in std::fs::read_dir(dir_path)? {
+        let entry = entry?;
+        if entry.file_name() == MANIFEST_FILE {
+            continue;
+        }
+        let name = Path::new(".").join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            tar.append_dir_all(&name, entry.path())?;
+        } else {
+            tar.append_path_with_name(entry.path(), &name)?;
+        }
+    }
     Ok(tar.into_inner()?.crc().sum())
 }
+
+/// Signs the package with the hex encoded ed25519 secret key in `key_path`,
+/// writing a detached signature to `<archive>.sig` next to its archive.
+/// The signature is over the package's `signed_payload`, so its SHA-256 must already be filled in.
+/// Returns the hex encoded signature and public key.
+pub fn sign_package(
+    package: &Package,
+    archive_path: &Path,
+    key_path: &Path,
+) -> Result<(String, String)> {
+    let key: [u8; 32] = std::fs::read_to_string(key_path)
+        .context("Could not read signing key")
+        .and_then(|key| hex::decode(key.trim()).context("Signing key is not valid hex"))?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signing key must be 32 bytes"))?;
+    let key = SigningKey::from_bytes(&key);
+
+    let payload = package.signed_payload()?;
+    let signature = hex::encode(key.sign(&payload).to_bytes());
+
+    let mut signature_path = archive_path.as_os_str().to_owned();
+    signature_path.push(".sig");
+    std::fs::write(&signature_path, &signature).context("Could not write signature file")?;
+
+    Ok((signature, hex::encode(key.verifying_key().to_bytes())))
+}
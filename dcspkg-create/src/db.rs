@@ -1,57 +1,73 @@
-use anyhow::{anyhow, Context, Result};
+use crate::Destination;
+use anyhow::{bail, Context, Result};
+use dcspkg::db::PackageStore;
 use dcspkg::Package;
-use sqlx::{
-    sqlite::{self, SqliteConnection},
-    Connection,
-};
-use std::path::Path;
-
-pub fn check_name_unique(db_path: &Path, pkg_name: &str) -> Result<()> {
-    smol::block_on(async { async_check_name_unique(db_path, pkg_name).await })
-}
+use semver::Version;
+use std::path::{Path, PathBuf};
+use tokio::runtime::{self, Runtime};
 
-pub fn add_package_to_db(db_path: &Path, package: Package) -> Result<()> {
-    smol::block_on(async { async_add_package_to_db(db_path, package).await })
+/// A blocking wrapper around the package database, since nothing else here is async,
+/// along with the directory the package archives are kept in
+pub struct Database {
+    runtime: Runtime,
+    store: PackageStore,
+    pkg_dir: PathBuf,
 }
 
-async fn async_check_name_unique(db_path: &Path, pkg_name: &str) -> Result<()> {
-    let mut connection = connect(db_path).await?;
-    let result: Result<Option<(String, String)>, sqlx::Error> =
-        sqlx::query_as("SELECT * FROM packages WHERE pkgname=?")
-            .bind(pkg_name)
-            .fetch_optional(&mut connection)
-            .await;
-
-    match result {
-        Ok(None) => Ok(()),
-        Err(e) => Err(e).context("Error in checking against database"),
-        Ok(Some(_)) => Err(anyhow!(
-            "Package with that name and version already exists in database"
-        )),
+impl Database {
+    pub fn open(db_path: &Path, pkg_dir: PathBuf) -> Result<Self> {
+        //sqlx's connection pool needs a tokio runtime
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to build runtime")?;
+        let path = db_path
+            .to_str()
+            .context("Could not convert database path to string")?;
+        let store = runtime.block_on(PackageStore::open(path))?;
+        Ok(Database {
+            runtime,
+            store,
+            pkg_dir,
+        })
     }
 }
 
-async fn async_add_package_to_db(db_path: &Path, package: Package) -> Result<()> {
-    let mut connection = connect(db_path).await?;
-    sqlx::query(
-        "INSERT INTO packages (pkgname, fullname, description, image_url, executable_path, crc, has_installer, add_to_path) VALUES (?,?,?,?,?,?,?,?)")
-        .bind(&package.pkgname)
-        .bind(&package.fullname)
-        .bind(&package.description)
-        .bind(&package.image_url)
-        .bind(&package.executable_path)
-        .bind(package.crc)
-        .bind(package.has_installer)
-        .bind(package.add_to_path)
-        .execute(&mut connection)
-        .await.context("Could not insert package into database").map(|_|()).map_err(Into::into)
-}
+impl Destination for Database {
+    fn check_version_unique(&self, pkg_name: &str, version: &Version) -> Result<()> {
+        let existing = self
+            .runtime
+            .block_on(self.store.get(pkg_name, version))
+            .context("Error in checking against database")?;
+
+        if existing.is_some() {
+            bail!("Package with that name and version already exists in database");
+        }
+        Ok(())
+    }
+
+    fn get_latest_version(&self, pkg_name: &str) -> Result<Option<Version>> {
+        self.runtime
+            .block_on(self.store.latest(pkg_name))
+            .map(|latest| latest.map(|pkg| pkg.version))
+            .context("Error in checking against database")
+    }
 
-async fn connect(path: &Path) -> Result<SqliteConnection> {
-    sqlite::SqliteConnection::connect(
-        path.to_str()
-            .ok_or_else(|| anyhow!("Could not convert database path to string"))?,
-    )
-    .await
-    .context("Could not connect to database")
+    fn get_tags(&self, pkg_name: &str) -> Result<Vec<String>> {
+        self.runtime
+            .block_on(self.store.tags(pkg_name))
+            .context("Error in getting tags from database")
+    }
+
+    fn archive_dir(&self) -> &Path {
+        &self.pkg_dir
+    }
+
+    fn add_package(&self, package: &Package, _archive_path: &Path) -> Result<()> {
+        self.runtime
+            .block_on(self.store.insert(package))
+            .context("Could not add package to database")?;
+        println!("Added package to database");
+        Ok(())
+    }
 }
@@ -1,53 +1,192 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::builder::ArgPredicate;
 use clap::Parser;
-use dcspkg::Package;
+use dcspkg::manifest::hash_file;
+use dcspkg::{Package, RunConfig};
+use manifest::{choose, Manifest, Overrides};
+use semver::Version;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
 mod archive;
 mod db;
+mod manifest;
 mod opts;
+mod publish;
+
+/// Where a package is added to, either a local database and package directory, or a server
+pub trait Destination {
+    fn get_latest_version(&self, pkg_name: &str) -> Result<Option<Version>>;
+    fn check_version_unique(&self, pkg_name: &str, version: &Version) -> Result<()>;
+    fn get_tags(&self, pkg_name: &str) -> Result<Vec<String>>;
+    /// The directory to build the package archive in
+    fn archive_dir(&self) -> &Path;
+    fn add_package(&self, package: &Package, archive_path: &Path) -> Result<()>;
+}
 
 fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
     let directory = PathBuf::from(&args.directory);
+    let yes = args.yes;
     println!("Creating new dcspkg from {directory:?}");
-    println!("Please specify package options (skip to use defaults)");
 
-    let pkgname = opts::get_pkg_name(directory.file_name().and_then(|s| s.to_str()))?;
+    let mut manifest = Manifest::load(&directory)?;
+    args.overrides.apply_to(&mut manifest);
+    manifest.validate(&directory)?;
+    if !yes {
+        println!("Please specify package options (skip to use defaults)");
+    }
 
-    db::check_name_unique(&args.db, &pkgname)?;
+    let db: Box<dyn Destination> = match (args.publish, args.db, args.pkg_dir) {
+        (Some(url), _, _) => Box::new(publish::Server::new(url, get_token(&args.token_file)?)?),
+        (None, Some(db), Some(pkg_dir)) => Box::new(db::Database::open(&db, pkg_dir)?),
+        _ => unreachable!("--db and --pkg-dir have defaults unless publishing"),
+    };
 
-    let fullname = opts::get_full_name(&pkgname)?;
-    let description = opts::get_description()?;
-    let image_url = opts::get_image_url()?;
-    let executable_path = opts::get_exe_path(&directory)?;
-    let add_to_path = opts::add_to_path()?;
-    let has_installer = opts::has_installer(&directory)?;
+    let dir_name = directory.file_name().and_then(|s| s.to_str());
+    let pkgname = choose(
+        manifest.name,
+        yes,
+        || match dir_name {
+            Some(name) => Ok(name.to_owned()),
+            None => bail!("No package name given, and the directory has no name to use"),
+        },
+        || opts::get_pkg_name(dir_name),
+    )?;
+    //the directory's name is used as is with --yes, so check it the same as a given one
+    opts::check_pkg_name(&pkgname).map_err(|e| anyhow!("Invalid package name {pkgname:?}: {e}"))?;
 
-    print!("Creating tarball...");
-    std::io::stdout().flush()?; //print with no newline so force a flush
+    let latest = db.get_latest_version(&pkgname)?;
+    if let Some(latest) = &latest {
+        println!("Latest version of {pkgname} is {latest}");
+    }
 
-    let archive_path = args.pkg_dir.join(format!("{pkgname}.dcspkg"));
+    let version = choose(
+        manifest.version,
+        yes,
+        || Ok(opts::default_version(latest.as_ref())),
+        || opts::get_version(latest.as_ref()),
+    )?;
 
-    let crc = archive::make_archive(&archive_path, &directory)?;
+    db.check_version_unique(&pkgname, &version)?;
 
-    println!("done!");
+    let fullname = choose(
+        manifest.fullname,
+        yes,
+        || Ok(pkgname.clone()),
+        || opts::get_full_name(&pkgname),
+    )?;
+    let description = choose(
+        manifest.description.map(Some),
+        yes,
+        || Ok(None),
+        opts::get_description,
+    )?;
+    let existing_tags = db.get_tags(&pkgname)?;
+    let tags = choose(
+        manifest.tags,
+        yes,
+        || Ok(existing_tags.clone()),
+        || opts::get_tags(&existing_tags),
+    )?;
+    let image_url = choose(
+        manifest.image_url.map(Some),
+        yes,
+        || Ok(None),
+        opts::get_image_url,
+    )?;
+    let executable_path = choose(
+        manifest.executable.map(Some),
+        yes,
+        || Ok(None),
+        || opts::get_exe_path(&directory),
+    )?;
+    //there's only something to add to path if there's an executable
+    let add_to_path = match (&executable_path, manifest.add_to_path) {
+        (Some(_), add_to_path) => choose(add_to_path, yes, || Ok(false), opts::add_to_path)?,
+        (None, Some(true)) => {
+            bail!("add_to_path is set, but the package has no executable to add to path")
+        }
+        (None, _) => false,
+    };
+    let bin_name = match (&executable_path, add_to_path) {
+        (Some(exe), true) => choose(
+            manifest.bin_name.map(Some),
+            yes,
+            || Ok(None),
+            || opts::get_bin_name(exe),
+        )?,
+        _ => None,
+    };
+    let entry_points = choose(
+        manifest.entry_points,
+        yes,
+        || Ok(vec![]),
+        || opts::get_entry_points(&directory),
+    )?;
+    let on_path = add_to_path || entry_points.iter().any(|entry| entry.add_to_path);
+    let run = choose(
+        manifest.run,
+        yes,
+        || Ok(RunConfig::default()),
+        || opts::get_run_config(&directory, on_path),
+    )?;
+    let has_installer = choose(
+        manifest.installer,
+        yes,
+        || Ok(opts::default_has_installer(&directory)),
+        || opts::has_installer(&directory),
+    )?;
 
-    let package = Package {
+    let mut package = Package {
         pkgname,
+        version,
         description,
         image_url,
         executable_path,
-        crc,
+        crc: 0,
+        sha256: None,
+        size: None,
+        updated: None,
+        yanked: false,
+        signature: None,
         has_installer,
         add_to_path,
+        bin_name,
+        entry_points,
+        run,
+        tags,
         fullname,
     };
 
+    print!("Creating tarball...");
+    std::io::stdout().flush()?; //print with no newline so force a flush
+
+    let archive_path = db.archive_dir().join(package.archive_name());
+
+    package.crc = archive::make_archive(&archive_path, &directory)?;
+    package.sha256 = Some(hash_file(&archive_path)?);
+    package.size = Some(std::fs::metadata(&archive_path)?.len());
+    package.updated = Some(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("System clock is before the unix epoch")?
+            .as_secs() as i64,
+    );
+
+    println!("done!");
+
+    if let Some(key_path) = &args.signing_key {
+        let (signature, public_key) = archive::sign_package(&package, &archive_path, key_path)?;
+        println!("Signed package with key {public_key}");
+        package.signature = Some(signature);
+    }
+
     println!("{}", serde_json::to_string_pretty(&package)?);
 
-    db::add_package_to_db(&args.db, package)?;
+    db.add_package(&package, &archive_path)?;
 
-    println!("Added package to database");
     println!("Your package is now ready for download!");
     Ok(())
 }
@@ -60,10 +199,38 @@ struct Cli {
     directory: PathBuf,
     #[arg(short, long, value_parser, value_parser=file_exists)]
     #[arg(default_value = "packages/packagedb.sqlite")]
-    db: PathBuf,
+    #[arg(default_value_if("publish", ArgPredicate::IsPresent, None))]
+    #[arg(conflicts_with = "publish")]
+    db: Option<PathBuf>,
     #[arg(short, long, value_parser, value_parser=dir_exists)]
     #[arg(default_value = "packages/packages")]
-    pkg_dir: PathBuf,
+    #[arg(default_value_if("publish", ArgPredicate::IsPresent, None))]
+    #[arg(conflicts_with = "publish")]
+    pkg_dir: Option<PathBuf>,
+    /// A file containing a hex encoded ed25519 secret key to sign the package with
+    #[arg(short, long, value_parser=file_exists)]
+    signing_key: Option<PathBuf>,
+    /// Never prompt, using the default for anything not given in dcspkg.toml or on the command line
+    #[arg(short, long)]
+    yes: bool,
+    #[command(flatten)]
+    overrides: Overrides,
+    /// Upload the package to the server at this URL, instead of adding it to a local database
+    #[arg(long, value_name = "URL")]
+    publish: Option<Url>,
+    /// A file containing the admin token for publishing, otherwise it's read from DCSPKG_ADMIN_TOKEN
+    #[arg(long, value_parser=file_exists, requires = "publish")]
+    token_file: Option<PathBuf>,
+}
+
+/// Gets the admin token for publishing, from `token_file` if given or the environment
+fn get_token(token_file: &Option<PathBuf>) -> Result<String> {
+    let token = match token_file {
+        Some(path) => std::fs::read_to_string(path).context("Could not read token file")?,
+        None => std::env::var("DCSPKG_ADMIN_TOKEN")
+            .context("Publishing needs an admin token, from --token-file or DCSPKG_ADMIN_TOKEN")?,
+    };
+    Ok(token.trim().to_owned())
 }
 
 fn dir_exists(f: &str) -> Result<PathBuf, &'static str> {
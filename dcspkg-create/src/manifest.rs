@@ -0,0 +1,164 @@
+use crate::opts;
+use anyhow::{anyhow, Context, Result};
+use clap::Args;
+use dcspkg::{EntryPoint, RunConfig};
+use semver::Version;
+use serde::Deserialize;
+use std::path::Path;
+
+/// The name of the manifest file, in the directory being packaged
+pub const MANIFEST_FILE: &str = "dcspkg.toml";
+
+/// Package options given in a `dcspkg.toml`, so packaging can be scripted.
+/// Anything left out is prompted for, or left as the default with `--yes`
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub name: Option<String>,
+    pub version: Option<Version>,
+    pub fullname: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    /// The relative path of the main executable
+    pub executable: Option<String>,
+    pub add_to_path: Option<bool>,
+    pub bin_name: Option<String>,
+    pub entry_points: Option<Vec<EntryPoint>>,
+    pub run: Option<RunConfig>,
+    /// Does the package have an install.sh that needs running?
+    pub installer: Option<bool>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl Manifest {
+    /// Reads the manifest from `dir`, or gives an empty one if there isn't one
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(Manifest::default());
+        }
+
+        println!("Reading package options from {path:?}");
+        let contents = std::fs::read_to_string(&path).context("Could not read manifest")?;
+        toml::from_str(&contents).context(format!("Could not parse {path:?}"))
+    }
+
+    /// Checks everything given is valid for the package in `dir`, the same as when prompting,
+    /// and normalises the tags
+    pub fn validate(&mut self, dir: &Path) -> Result<()> {
+        let invalid = |field: &str, e: &str| anyhow!("Invalid {field} in package options: {e}");
+
+        if let Some(name) = &self.name {
+            opts::check_pkg_name(name).map_err(|e| invalid("name", e))?;
+        }
+        if let Some(image_url) = &self.image_url {
+            url::Url::parse(image_url).map_err(|e| invalid("image_url", &e.to_string()))?;
+        }
+        if let Some(exe) = &self.executable {
+            opts::check_exe_path(dir, exe).map_err(|e| invalid("executable", e))?;
+        }
+        if let Some(bin_name) = &self.bin_name {
+            opts::check_bin_name(bin_name).map_err(|e| invalid("bin_name", e))?;
+        }
+        if let Some(entry_points) = &self.entry_points {
+            for (i, entry) in entry_points.iter().enumerate() {
+                opts::check_entry_point_name(&entry.name)
+                    .and_then(|_| opts::check_exe_path(dir, &entry.path))
+                    .map_err(|e| invalid("entry_points", &format!("{}: {e}", entry.name)))?;
+                if entry_points[..i]
+                    .iter()
+                    .any(|other| other.name == entry.name)
+                {
+                    return Err(invalid("entry_points", "names must be unique"));
+                }
+            }
+        }
+        if let Some(run) = &self.run {
+            if let Some(cwd) = &run.cwd {
+                opts::check_cwd(dir, cwd).map_err(|e| invalid("run.cwd", e))?;
+            }
+            for name in run.env.keys() {
+                opts::check_env_name(name)
+                    .map_err(|e| invalid("run.env", &format!("{name:?}: {e}")))?;
+            }
+        }
+        if self.installer == Some(true) {
+            opts::check_installer(dir)?;
+        }
+        if let Some(tags) = &self.tags {
+            let tags = opts::parse_tags(tags.iter().map(String::as_str))
+                .map_err(|e| invalid("tags", e))?;
+            self.tags = Some(tags);
+        }
+        Ok(())
+    }
+}
+
+/// Command line flags that override the manifest
+#[derive(Args, Debug)]
+pub struct Overrides {
+    /// The package's short name
+    #[arg(long)]
+    name: Option<String>,
+    /// The package's version
+    #[arg(long, value_name = "VERSION")]
+    pkg_version: Option<Version>,
+    /// The full application name or game title
+    #[arg(long)]
+    fullname: Option<String>,
+    #[arg(long)]
+    description: Option<String>,
+    #[arg(long)]
+    image_url: Option<String>,
+    /// The relative path of the executable within the package
+    #[arg(long, value_name = "PATH")]
+    executable: Option<String>,
+    /// Add the executable to the user's path on installation?
+    #[arg(long, value_name = "BOOL")]
+    add_to_path: Option<bool>,
+    /// The name to add the executable to path as
+    #[arg(long)]
+    bin_name: Option<String>,
+    /// Does the package have an install.sh script?
+    #[arg(long, value_name = "BOOL")]
+    installer: Option<bool>,
+    /// Comma separated tags, ie games,multiplayer
+    #[arg(long, value_delimiter = ',')]
+    tags: Option<Vec<String>>,
+}
+
+impl Overrides {
+    pub fn apply_to(self, manifest: &mut Manifest) {
+        fn set<T>(field: &mut Option<T>, value: Option<T>) {
+            if value.is_some() {
+                *field = value;
+            }
+        }
+
+        set(&mut manifest.name, self.name);
+        set(&mut manifest.version, self.pkg_version);
+        set(&mut manifest.fullname, self.fullname);
+        set(&mut manifest.description, self.description);
+        set(&mut manifest.image_url, self.image_url);
+        set(&mut manifest.executable, self.executable);
+        set(&mut manifest.add_to_path, self.add_to_path);
+        set(&mut manifest.bin_name, self.bin_name);
+        set(&mut manifest.installer, self.installer);
+        set(&mut manifest.tags, self.tags);
+    }
+}
+
+/// Uses the given value if there is one. Otherwise prompts for it,
+/// or uses the default without asking if `yes` is set
+pub fn choose<T>(
+    given: Option<T>,
+    yes: bool,
+    default: impl FnOnce() -> Result<T>,
+    prompt: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    match given {
+        Some(value) => Ok(value),
+        None if yes => default(),
+        None => prompt(),
+    }
+}
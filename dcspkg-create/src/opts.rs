@@ -1,5 +1,8 @@
 use anyhow::{bail, Context, Result};
+use dcspkg::{EntryPoint, RunConfig};
 use dialoguer::{theme::ColorfulTheme, Input, Select};
+use semver::Version;
+use std::collections::BTreeMap;
 use std::path::Path;
 
 pub fn get_pkg_name(default: Option<&str>) -> Result<String> {
@@ -8,16 +11,53 @@ pub fn get_pkg_name(default: Option<&str>) -> Result<String> {
             .with_prompt("Enter package short name")
             .default(default.to_string())
             .show_default(true)
+            .validate_with(|input: &String| check_pkg_name(input))
             .interact_text()
     } else {
         Input::with_theme(&ColorfulTheme::default())
             .with_prompt("Enter package short name")
             .show_default(true)
+            .validate_with(|input: &String| check_pkg_name(input))
             .interact_text()
     }
     .context("Could not get package name")
 }
 
+/// Package names end up in file names, so are kept to the same safe set of characters the server allows
+pub fn check_pkg_name(name: &str) -> Result<(), &'static str> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err("names may only contain letters, numbers, -, _ and ., and can't start with .")
+    }
+}
+
+/// The next patch version if there is already one, otherwise 0.1.0
+pub fn default_version(latest: Option<&Version>) -> Version {
+    match latest {
+        Some(latest) => Version::new(latest.major, latest.minor, latest.patch + 1),
+        None => Version::new(0, 1, 0),
+    }
+}
+
+pub fn get_version(latest: Option<&Version>) -> Result<Version> {
+    let default = default_version(latest);
+
+    Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter package version")
+        .default(default.to_string())
+        .show_default(true)
+        .validate_with(|input: &String| input.parse::<Version>().map(|_| ()))
+        .interact_text()
+        .context("Could not get package version")
+        .and_then(|input| input.parse().context("Could not parse package version"))
+}
+
 pub fn get_full_name(default: &str) -> Result<String> {
     Input::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter full application name or game title")
@@ -36,6 +76,41 @@ pub fn get_description() -> Result<Option<String>> {
         .context("Could not get description")
 }
 
+pub fn get_tags(existing: &[String]) -> Result<Vec<String>> {
+    Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter tags for the package, separated by commas, ie games, multiplayer")
+        .default(existing.join(", "))
+        .show_default(!existing.is_empty())
+        .allow_empty(true)
+        .validate_with(|input: &String| parse_tags(input.split(',')).map(|_| ()))
+        .interact_text()
+        .map(|input| parse_tags(input.split(',')).unwrap_or_default())
+        .context("Could not get tags")
+}
+
+/// Trims, lowercases, sorts and dedups tags, checking they're valid
+pub fn parse_tags<'a>(
+    tags: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<String>, &'static str> {
+    let mut tags: Vec<String> = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    let valid = tags.iter().all(|tag| {
+        tag.chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    });
+    if !valid {
+        return Err("tags may only contain letters, numbers, - and _");
+    }
+
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
 pub fn get_image_url() -> Result<Option<String>> {
     Input::<String>::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter URL for image")
@@ -56,17 +131,20 @@ pub fn get_exe_path(base_dir: &Path) -> Result<Option<String>> {
     Input::<String>::with_theme(&ColorfulTheme::default())
         .with_prompt("Enter the relative path of the executable within this package")
         .allow_empty(true)
-        .validate_with(|input: &String| {
-            let path = base_dir.join(input);
-            path.is_file()
-                .then_some(())
-                .ok_or("executable specified does not exist")
-        })
+        .validate_with(|input: &String| check_exe_path(base_dir, input))
         .interact_text()
         .map(|input| if input.is_empty() { None } else { Some(input) })
         .context("Could not get executable path")
 }
 
+pub fn check_exe_path(base_dir: &Path, path: &str) -> Result<(), &'static str> {
+    base_dir
+        .join(path)
+        .is_file()
+        .then_some(())
+        .ok_or("executable specified does not exist")
+}
+
 pub fn add_to_path() -> Result<bool> {
     Select::with_theme(&ColorfulTheme::default())
         .with_prompt(
@@ -83,11 +161,177 @@ pub fn add_to_path() -> Result<bool> {
         .context("Could not get choice for adding executable to path")
 }
 
+pub fn get_bin_name(exe_path: &str) -> Result<Option<String>> {
+    //the executable's own name is used by default, so there's no need to store it
+    let default = Path::new(exe_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter the name to add the executable to path as")
+        .default(default.clone())
+        .show_default(true)
+        .validate_with(|input: &String| check_bin_name(input))
+        .interact_text()
+        .map(|input| if input == default { None } else { Some(input) })
+        .context("Could not get name for executable on path")
+}
+
+pub fn check_bin_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        Err("name must be a plain file name")
+    } else {
+        Ok(())
+    }
+}
+
+pub fn get_entry_points(base_dir: &Path) -> Result<Vec<EntryPoint>> {
+    let mut entry_points: Vec<EntryPoint> = vec![];
+
+    loop {
+        let name = Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt("Enter the name of another entry point (skip if there are no more)")
+            .allow_empty(true)
+            .validate_with(|input: &String| {
+                if input.is_empty() {
+                    Ok(())
+                } else if entry_points.iter().any(|entry| entry.name == *input) {
+                    Err("an entry point with that name already exists")
+                } else {
+                    check_entry_point_name(input)
+                }
+            })
+            .interact_text()
+            .context("Could not get entry point name")?;
+
+        if name.is_empty() {
+            return Ok(entry_points);
+        }
+
+        let path = Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Enter the relative path of the {name} executable within this package"
+            ))
+            .validate_with(|input: &String| check_exe_path(base_dir, input))
+            .interact_text()
+            .context("Could not get entry point path")?;
+
+        let add_to_path = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Do you wish for {name} to be added to the user's path on installation?"
+            ))
+            .items(&["yes", "no"])
+            .default(1)
+            .interact()
+            .map(|selection| selection == 0)
+            .context("Could not get choice for adding entry point to path")?;
+
+        entry_points.push(EntryPoint {
+            name,
+            path,
+            add_to_path,
+        });
+    }
+}
+
+pub fn check_entry_point_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name.contains(['/', ':']) || name == "." || name == ".." {
+        Err("name must be a plain file name, without a colon")
+    } else {
+        Ok(())
+    }
+}
+
+pub fn get_run_config(base_dir: &Path, on_path: bool) -> Result<RunConfig> {
+    let cwd = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt(
+            "Enter the directory to run from, relative to this package (skip to use the user's)",
+        )
+        .allow_empty(true)
+        .validate_with(|input: &String| {
+            if input.is_empty() {
+                Ok(())
+            } else {
+                check_cwd(base_dir, input)
+            }
+        })
+        .interact_text()
+        .map(|input| if input.is_empty() { None } else { Some(input) })
+        .context("Could not get working directory")?;
+
+    let mut env = BTreeMap::new();
+    loop {
+        let var = Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt("Enter an environment variable to set as KEY=VALUE, where ${PKGDIR} is the package's directory (skip if there are no more)")
+            .allow_empty(true)
+            .validate_with(|input: &String| {
+                if input.is_empty() {
+                    return Ok(());
+                }
+                match input.split_once('=') {
+                    Some((key, _)) => check_env_name(key),
+                    None => Err("must be of the form KEY=VALUE"),
+                }
+            })
+            .interact_text()
+            .context("Could not get environment variable")?;
+
+        match var.split_once('=') {
+            Some((key, value)) => env.insert(key.to_string(), value.to_string()),
+            None => break,
+        };
+    }
+
+    let args: Vec<String> = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter default arguments, separated by spaces")
+        .allow_empty(true)
+        .interact_text()
+        .map(|input| input.split_whitespace().map(str::to_string).collect())
+        .context("Could not get default arguments")?;
+
+    //a launcher is only worth having if there is something for it to set up
+    let launcher = if on_path && (cwd.is_some() || !env.is_empty() || !args.is_empty()) {
+        Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Add executables to path with a launcher script that sets these up, rather than a symlink?")
+            .items(&["yes", "no"])
+            .default(0)
+            .interact()
+            .map(|selection| selection == 0)
+            .context("Could not get choice for launcher script")?
+    } else {
+        false
+    };
+
+    Ok(RunConfig {
+        cwd,
+        env,
+        args,
+        launcher,
+    })
+}
+
+pub fn check_env_name(name: &str) -> Result<(), &'static str> {
+    if RunConfig::is_valid_env_name(name) {
+        Ok(())
+    } else {
+        Err("variable names may only contain letters, numbers and _, and can't start with a number")
+    }
+}
+
+pub fn check_cwd(base_dir: &Path, cwd: &str) -> Result<(), &'static str> {
+    base_dir
+        .join(cwd)
+        .is_dir()
+        .then_some(())
+        .ok_or("directory specified does not exist")
+}
+
 pub fn has_installer(dir: &Path) -> Result<bool> {
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Does this executable have an install.sh script?")
         .items(&["yes", "no"])
-        .default(1)
+        .default(if default_has_installer(dir) { 0 } else { 1 })
         .interact()
         .map(|selection| match selection {
             0 => true,
@@ -95,10 +339,22 @@ pub fn has_installer(dir: &Path) -> Result<bool> {
             _ => unreachable!(),
         })
         .context("Could not get choice for install script")?;
-    let script_path = dir.join("install.sh");
 
-    if selection && !script_path.is_file() {
-        bail!("Could not find install script at {script_path:?}")
+    if selection {
+        check_installer(dir)?;
     }
     Ok(selection)
 }
+
+/// Packages are assumed to have an installer if they contain an install.sh
+pub fn default_has_installer(dir: &Path) -> bool {
+    dir.join("install.sh").is_file()
+}
+
+pub fn check_installer(dir: &Path) -> Result<()> {
+    let script_path = dir.join("install.sh");
+    if !script_path.is_file() {
+        bail!("Could not find install script at {script_path:?}")
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use crate::Destination;
+use anyhow::{anyhow, bail, Context, Result};
+use dcspkg::Package;
+use reqwest::blocking::{multipart::Form, Client, Response};
+use reqwest::StatusCode;
+use semver::Version;
+use serde::Deserialize;
+use std::path::Path;
+use tempfile::TempDir;
+use url::Url;
+
+/// A server to publish packages to through its admin API, instead of a local database
+pub struct Server {
+    client: Client,
+    url: Url,
+    token: String,
+    /// Where the archive is built before it's uploaded
+    build_dir: TempDir,
+}
+
+/// The JSON body the server sends with an error
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl Server {
+    pub fn new(url: Url, token: String) -> Result<Self> {
+        //uploads can be big, so don't give up on them
+        let client = Client::builder()
+            .timeout(None)
+            .build()
+            .context("Could not build HTTP client")?;
+        let build_dir = tempfile::tempdir().context("Could not create temporary directory")?;
+        Ok(Server {
+            client,
+            url,
+            token,
+            build_dir,
+        })
+    }
+
+    /// Every version of the package on the server, including yanked ones
+    fn get_versions(&self, pkg_name: &str) -> Result<Vec<Package>> {
+        let url = self
+            .url
+            .join(&format!("/versions/{pkg_name}"))
+            .context("Could not parse URL")?;
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .context("Could not get package versions from server")?;
+
+        match response.status() {
+            StatusCode::OK => response.json().context("Could not parse JSON response"),
+            StatusCode::NOT_FOUND => Ok(vec![]),
+            _ => Err(server_error(response)),
+        }
+    }
+}
+
+impl Destination for Server {
+    fn get_latest_version(&self, pkg_name: &str) -> Result<Option<Version>> {
+        //yanked versions are counted too, as their version can't be reused
+        Ok(self
+            .get_versions(pkg_name)?
+            .into_iter()
+            .map(|pkg| pkg.version)
+            .max())
+    }
+
+    fn check_version_unique(&self, pkg_name: &str, version: &Version) -> Result<()> {
+        if self
+            .get_versions(pkg_name)?
+            .iter()
+            .any(|pkg| &pkg.version == version)
+        {
+            bail!("Package with that name and version already exists on the server");
+        }
+        Ok(())
+    }
+
+    fn get_tags(&self, pkg_name: &str) -> Result<Vec<String>> {
+        //tags belong to the package, so every version has the same ones
+        Ok(self
+            .get_versions(pkg_name)?
+            .pop()
+            .map(|pkg| pkg.tags)
+            .unwrap_or_default())
+    }
+
+    fn archive_dir(&self) -> &Path {
+        self.build_dir.path()
+    }
+
+    fn add_package(&self, package: &Package, archive_path: &Path) -> Result<()> {
+        let url = self
+            .url
+            .join(&format!(
+                "/admin/packages/{}/{}",
+                package.pkgname, package.version
+            ))
+            .context("Could not parse URL")?;
+
+        //the server works out the checksums and size itself, the rest is sent as the metadata
+        let mut form = Form::new()
+            .text("metadata", serde_json::to_string(package)?)
+            .file("package", archive_path)
+            .context("Could not read archive")?;
+        if let Some(signature) = &package.signature {
+            form = form.text("signature", signature.clone());
+        }
+
+        println!("Uploading package to {}...", self.url);
+        let response = self
+            .client
+            .post(url)
+            .bearer_auth(&self.token)
+            .multipart(form)
+            .send()
+            .context("Could not upload package")?;
+
+        if response.status() != StatusCode::CREATED {
+            return Err(server_error(response)).context("Server did not accept package");
+        }
+
+        println!("Published package to {}", self.url);
+        Ok(())
+    }
+}
+
+/// Turns an error response into an error, with the reason the server gave if there is one
+fn server_error(response: Response) -> anyhow::Error {
+    let status = response.status();
+    match response.json::<ErrorBody>() {
+        Ok(body) => anyhow!("{} (code {})", body.error, status.as_u16()),
+        Err(_) => anyhow!(
+            "Response from server was not okay (code {})",
+            status.as_u16()
+        ),
+    }
+}
@@ -0,0 +1,431 @@
+use crate::auth::Admin;
+use crate::error::{is_unique_violation, Error};
+use dcspkg::db::PackageStore;
+use dcspkg::manifest::hash_file;
+use dcspkg::{EntryPoint, Package, RunConfig};
+use flate2::read::GzDecoder;
+use flate2::CrcReader;
+use rocket::form::{Errors, Form};
+use rocket::fs::TempFile;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use rocket::tokio::{fs, task};
+use rocket::{delete, post, put, FromForm, State};
+use semver::Version;
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory the package archives are kept in, and served from
+pub struct PackageDir(pub PathBuf);
+
+/// The metadata of a package that's given by whoever uploads it,
+/// rather than worked out from the archive. A whole `Package` can be sent as this
+#[derive(Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Metadata {
+    pub fullname: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub executable_path: Option<String>,
+    #[serde(default)]
+    pub has_installer: bool,
+    #[serde(default)]
+    pub add_to_path: bool,
+    #[serde(default)]
+    pub bin_name: Option<String>,
+    #[serde(default)]
+    pub entry_points: Vec<EntryPoint>,
+    #[serde(default)]
+    pub run: RunConfig,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Metadata {
+    /// Sets the package's metadata to this, checking it's valid
+    fn apply_to(self, pkg: &mut Package) -> Result<(), Error> {
+        pkg.tags = normalise_tags(self.tags)?;
+        pkg.fullname = self.fullname;
+        pkg.description = self.description;
+        pkg.image_url = self.image_url;
+        pkg.executable_path = self.executable_path;
+        pkg.has_installer = self.has_installer;
+        pkg.add_to_path = self.add_to_path;
+        pkg.bin_name = self.bin_name;
+        pkg.entry_points = self.entry_points;
+        pkg.run = self.run;
+        Ok(())
+    }
+}
+
+/// A package archive, along with its metadata as JSON
+#[derive(FromForm)]
+pub struct Upload<'r> {
+    package: TempFile<'r>,
+    metadata: Json<Metadata>,
+    /// The hex encoded ed25519 signature of the archive, if it has been signed
+    signature: Option<String>,
+}
+
+#[post("/admin/packages/<name>/<version>", data = "<upload>")]
+pub async fn upload(
+    _admin: Admin,
+    db: &State<PackageStore>,
+    dir: &State<PackageDir>,
+    name: &str,
+    version: &str,
+    upload: Result<Form<Upload<'_>>, Errors<'_>>,
+) -> Result<(Status, Json<Package>), Error> {
+    let version = parse_version(version)?;
+    check_name(name)?;
+    let Upload {
+        mut package,
+        metadata,
+        signature,
+    } = upload
+        .map_err(|errors| {
+            let reasons: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            Error::BadRequest(format!("Invalid upload: {}", reasons.join(", ")))
+        })?
+        .into_inner();
+
+    if let Some(signature) = &signature {
+        if signature.len() != 128 || !signature.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::BadRequest(
+                "Signature must be 64 hex encoded bytes".to_owned(),
+            ));
+        }
+    }
+
+    if db.get(name, &version).await?.is_some() {
+        return Err(Error::Conflict(format!(
+            "Package {name} already has a version {version}"
+        )));
+    }
+
+    let mut pkg = Package {
+        pkgname: name.to_owned(),
+        version,
+        fullname: String::new(),
+        description: None,
+        image_url: None,
+        executable_path: None,
+        crc: 0,
+        sha256: None,
+        signature,
+        has_installer: false,
+        add_to_path: false,
+        bin_name: None,
+        entry_points: vec![],
+        run: RunConfig::default(),
+        tags: vec![],
+        size: None,
+        updated: None,
+        yanked: false,
+    };
+    metadata.into_inner().apply_to(&mut pkg)?;
+
+    let archive_path = dir.0.join(pkg.archive_name());
+    if fs::try_exists(&archive_path)
+        .await
+        .map_err(|e| Error::Internal(format!("Could not check package directory: {e}")))?
+    {
+        return Err(Error::Conflict(format!(
+            "{} already exists in the package directory",
+            pkg.archive_name()
+        )));
+    }
+
+    //each upload gets its own file, so concurrent ones can't overwrite each other.
+    //a dotfile, so the file server won't serve it, and removed when dropped unless moved into place
+    let upload_file = tempfile::Builder::new()
+        .prefix(&format!(".{}.", pkg.archive_name()))
+        .suffix(".upload")
+        .tempfile_in(&dir.0)
+        .map_err(|e| Error::Internal(format!("Could not create file for upload: {e}")))?;
+    package
+        .persist_to(upload_file.path())
+        .await
+        .map_err(|e| Error::Internal(format!("Could not save upload: {e}")))?;
+
+    add_upload(db, &mut pkg, upload_file.path(), &archive_path).await?;
+
+    log::info!("Added package {} version {}", pkg.pkgname, pkg.version);
+    Ok((Status::Created, Json(pkg)))
+}
+
+/// Checks the uploaded archive and fills in what's worked out from it,
+/// then adds the package to the database and moves the archive into place together
+async fn add_upload(
+    db: &PackageStore,
+    pkg: &mut Package,
+    upload_path: &Path,
+    archive_path: &Path,
+) -> Result<(), Error> {
+    let (crc, sha256, size) = inspect_archive(upload_path.to_owned()).await?;
+    pkg.crc = crc;
+    pkg.sha256 = Some(sha256);
+    pkg.size = Some(size);
+    pkg.updated = Some(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| Error::Internal(format!("System clock is wrong: {e}")))?
+            .as_secs() as i64,
+    );
+
+    //two uploads of the same version can both get past the check for an existing one,
+    //so whichever loses the race is told about the conflict here
+    let mut transaction = db.begin().await?;
+    transaction.insert(pkg).await.map_err(|e| {
+        if is_unique_violation(&e) {
+            Error::Conflict(format!(
+                "Package {} already has a version {}",
+                pkg.pkgname, pkg.version
+            ))
+        } else {
+            e.into()
+        }
+    })?;
+
+    let signature_path = signature_path(archive_path);
+    if let Some(signature) = &pkg.signature {
+        if let Err(e) = fs::write(&signature_path, signature).await {
+            let _ = fs::remove_file(&signature_path).await;
+            return Err(Error::Internal(format!("Could not write signature: {e}")));
+        }
+    }
+    //uploads are only readable by the server, but archives should be readable by anyone
+    let moved = match fs::set_permissions(upload_path, Permissions::from_mode(0o644)).await {
+        Ok(()) => fs::rename(upload_path, archive_path).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = moved {
+        let _ = fs::remove_file(&signature_path).await;
+        return Err(Error::Internal(format!(
+            "Could not move upload into place: {e}"
+        )));
+    }
+
+    //put the archive back where it was if the database didn't take it, so nothing is left half added
+    if let Err(e) = transaction.commit().await {
+        let _ = fs::rename(archive_path, upload_path).await;
+        let _ = fs::remove_file(&signature_path).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Works out the CRC of the uncompressed tarball, the SHA-256 of the archive, and its size.
+/// Fails if it isn't a gzipped tarball
+async fn inspect_archive(path: PathBuf) -> Result<(u32, String, u64), Error> {
+    task::spawn_blocking(move || {
+        let internal = |e: io::Error| Error::Internal(format!("Could not read upload: {e}"));
+
+        let file = std::fs::File::open(&path).map_err(internal)?;
+        let mut reader = CrcReader::new(GzDecoder::new(file));
+        io::copy(&mut reader, &mut io::sink())
+            .map_err(|e| Error::BadRequest(format!("Package is not a gzipped tarball: {e}")))?;
+
+        let sha256 = hash_file(&path).map_err(internal)?;
+        let size = std::fs::metadata(&path).map_err(internal)?.len();
+        Ok((reader.crc().sum(), sha256, size))
+    })
+    .await
+    .map_err(|e| Error::Internal(format!("Could not inspect upload: {e}")))?
+}
+
+#[put("/admin/packages/<name>/<version>", data = "<metadata>")]
+pub async fn update(
+    _admin: Admin,
+    db: &State<PackageStore>,
+    name: &str,
+    version: &str,
+    metadata: Result<Json<Metadata>, rocket::serde::json::Error<'_>>,
+) -> Result<Json<Package>, Error> {
+    let version = parse_version(version)?;
+    let metadata = metadata.map_err(|e| Error::BadRequest(format!("Invalid metadata: {e}")))?;
+
+    let mut pkg = db
+        .get(name, &version)
+        .await?
+        .ok_or_else(|| not_found(name, &version))?;
+    let signed = pkg.signature.as_ref().map(|_| pkg.signed_payload().ok());
+    metadata.into_inner().apply_to(&mut pkg)?;
+
+    //the signature covers how the package is installed and run, so only the rest can be changed
+    if signed.map_or(false, |before| before != pkg.signed_payload().ok()) {
+        return Err(Error::Conflict(format!(
+            "Package {name} version {version} is signed, and the signature would no longer match. Upload a new version instead"
+        )));
+    }
+
+    if !db.update(&pkg).await? {
+        return Err(not_found(name, &version));
+    }
+
+    log::info!("Updated package {name} version {version}");
+    Ok(Json(pkg))
+}
+
+#[delete("/admin/packages/<name>/<version>")]
+pub async fn remove(
+    _admin: Admin,
+    db: &State<PackageStore>,
+    dir: &State<PackageDir>,
+    name: &str,
+    version: &str,
+) -> Result<Json<Package>, Error> {
+    let version = parse_version(version)?;
+    let pkg = db
+        .get(name, &version)
+        .await?
+        .ok_or_else(|| not_found(name, &version))?;
+
+    let mut transaction = db.begin().await?;
+    if !transaction.delete(name, &version).await? {
+        return Err(not_found(name, &version));
+    }
+
+    //move the files aside rather than deleting them, so they can be put back if the database fails
+    let archive_path = dir.0.join(pkg.archive_name());
+    let files = [signature_path(&archive_path), archive_path];
+    let mut moved = vec![];
+    for path in &files {
+        let removed_path = removed_path(path);
+        match fs::rename(path, &removed_path).await {
+            Ok(()) => moved.push((path, removed_path)),
+            //it's already gone, which is what we want
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => {
+                restore(&moved).await;
+                return Err(Error::Internal(format!("Could not remove {path:?}: {e}")));
+            }
+        }
+    }
+
+    if let Err(e) = transaction.commit().await {
+        restore(&moved).await;
+        return Err(e.into());
+    }
+    for (_, removed_path) in &moved {
+        let _ = fs::remove_file(removed_path).await;
+    }
+
+    log::info!("Deleted package {name} version {version}");
+    Ok(Json(pkg))
+}
+
+/// Puts back files that were moved aside
+async fn restore(moved: &[(&PathBuf, PathBuf)]) {
+    for (path, removed_path) in moved {
+        let _ = fs::rename(removed_path, path).await;
+    }
+}
+
+#[post("/admin/packages/<name>/<version>/yank")]
+pub async fn yank(
+    _admin: Admin,
+    db: &State<PackageStore>,
+    name: &str,
+    version: &str,
+) -> Result<Json<Package>, Error> {
+    set_yanked(db, name, version, true).await
+}
+
+#[post("/admin/packages/<name>/<version>/unyank")]
+pub async fn unyank(
+    _admin: Admin,
+    db: &State<PackageStore>,
+    name: &str,
+    version: &str,
+) -> Result<Json<Package>, Error> {
+    set_yanked(db, name, version, false).await
+}
+
+async fn set_yanked(
+    db: &PackageStore,
+    name: &str,
+    version: &str,
+    yanked: bool,
+) -> Result<Json<Package>, Error> {
+    let version = parse_version(version)?;
+    if !db.set_yanked(name, &version, yanked).await? {
+        return Err(not_found(name, &version));
+    }
+
+    log::info!("Set package {name} version {version} yanked to {yanked}");
+    db.get(name, &version)
+        .await?
+        .map(Json)
+        .ok_or_else(|| not_found(name, &version))
+}
+
+fn parse_version(version: &str) -> Result<Version, Error> {
+    version
+        .parse()
+        .map_err(|e| Error::BadRequest(format!("Invalid version {version:?}: {e}")))
+}
+
+/// Package names end up in file names, so are kept to a safe set of characters
+fn check_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::BadRequest(format!(
+            "Invalid package name {name:?}, names may only contain letters, numbers, -, _ and ."
+        )))
+    }
+}
+
+/// Lowercases, sorts and dedups tags, the same as `dcspkg-create` does
+fn normalise_tags(tags: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    if let Some(tag) = tags.iter().find(|tag| {
+        !tag.chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    }) {
+        return Err(Error::BadRequest(format!(
+            "Invalid tag {tag:?}, tags may only contain letters, numbers, - and _"
+        )));
+    }
+
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+fn not_found(name: &str, version: &Version) -> Error {
+    Error::NotFound(format!(
+        "Package {name} does not exist, or has no version {version}"
+    ))
+}
+
+/// Where the detached signature for an archive is kept
+fn signature_path(archive_path: &Path) -> PathBuf {
+    let mut path = archive_path.as_os_str().to_owned();
+    path.push(".sig");
+    path.into()
+}
+
+/// Where a file is moved to while it's being deleted. A dotfile, so the file server won't serve it
+fn removed_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.removed"))
+}
@@ -0,0 +1,174 @@
+use crate::error::GuardError;
+use anyhow::{Context, Result};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+/// The tokens that are allowed to use the admin API
+#[derive(Default)]
+pub struct Tokens(Vec<String>);
+
+impl Tokens {
+    /// Loads tokens from the comma separated `ADMIN_TOKENS`, and from the file named by `ADMIN_TOKENS_FILE`,
+    /// which has one per line. Blank lines and lines starting with `#` in the file are ignored
+    pub fn from_env() -> Result<Self> {
+        let mut tokens = parse_list(&std::env::var("ADMIN_TOKENS").unwrap_or_default());
+
+        if let Ok(path) = std::env::var("ADMIN_TOKENS_FILE") {
+            let file = std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read admin tokens from {path}"))?;
+            tokens.extend(parse_file(&file));
+        }
+
+        Ok(Tokens(tokens))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn contains(&self, token: &str) -> bool {
+        //check them all, so the time taken doesn't give away which one nearly matched
+        self.0.iter().fold(false, |found, valid| {
+            found | same(valid.as_bytes(), token.as_bytes())
+        })
+    }
+}
+
+/// Splits a comma separated list of tokens
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|token| token.trim().to_owned())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Reads a file of tokens, one per line, skipping blank lines and `#` comments
+fn parse_file(file: &str) -> Vec<String> {
+    file.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Compares every byte, so the time taken doesn't give away how much of a token was right
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// A request guard for the admin API, which needs an `Authorization: Bearer <token>` header
+/// with one of the configured tokens. Checked before any uploaded data is read
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let tokens = match request.rocket().state::<Tokens>() {
+            Some(tokens) if !tokens.is_empty() => tokens,
+            _ => return unauthorized(request, "The admin API is not enabled"),
+        };
+
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+
+        match token {
+            Some(token) if tokens.contains(token.trim()) => Outcome::Success(Admin),
+            Some(_) => unauthorized(request, "Invalid admin token"),
+            None => unauthorized(request, "An admin token is needed for this"),
+        }
+    }
+}
+
+/// Fails the request before its body is read, passing the reason on to the catcher
+fn unauthorized(request: &Request<'_>, message: &str) -> Outcome<Admin, ()> {
+    request.local_cache(|| GuardError(Some(message.to_owned())));
+    Outcome::Error((Status::Unauthorized, ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::default_catcher;
+    use rocket::local::blocking::Client;
+    use rocket::{catchers, get, routes};
+
+    #[test]
+    fn parses_token_list() {
+        assert_eq!(parse_list(" one, ,two ,"), ["one", "two"]);
+        assert!(parse_list("").is_empty());
+    }
+
+    #[test]
+    fn parses_token_file() {
+        let file = "# admins\none\n\n  two  \n#three\n";
+        assert_eq!(parse_file(file), ["one", "two"]);
+    }
+
+    #[test]
+    fn matches_whole_tokens() {
+        let tokens = Tokens(vec!["secret".to_owned(), "other".to_owned()]);
+        assert!(tokens.contains("secret"));
+        assert!(tokens.contains("other"));
+        assert!(!tokens.contains("secre"));
+        assert!(!tokens.contains("secrets"));
+        assert!(!tokens.contains(""));
+    }
+
+    #[get("/admin")]
+    fn guarded(_admin: Admin) -> &'static str {
+        "ok"
+    }
+
+    fn client(tokens: Tokens) -> Client {
+        let rocket = rocket::build()
+            .manage(tokens)
+            .mount("/", routes![guarded])
+            .register("/", catchers![default_catcher]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    /// Makes a request to the guarded route, returning the status and body
+    fn get(client: &Client, authorization: Option<&str>) -> (Status, String) {
+        let mut request = client.get("/admin");
+        if let Some(authorization) = authorization {
+            request = request.header(rocket::http::Header::new(
+                "Authorization",
+                authorization.to_owned(),
+            ));
+        }
+        let response = request.dispatch();
+        (
+            response.status(),
+            response.into_string().unwrap_or_default(),
+        )
+    }
+
+    #[test]
+    fn admin_needs_a_valid_token() {
+        let client = client(Tokens(vec!["secret".to_owned()]));
+
+        assert_eq!(get(&client, Some("Bearer secret")).0, Status::Ok);
+
+        let (status, body) = get(&client, None);
+        assert_eq!(status, Status::Unauthorized);
+        assert!(body.contains("An admin token is needed"), "{body}");
+
+        for header in ["Bearer wrong", "Bearer ", "secret", "Basic secret"] {
+            let (status, _) = get(&client, Some(header));
+            assert_eq!(status, Status::Unauthorized, "{header} was let through");
+        }
+    }
+
+    #[test]
+    fn admin_is_disabled_without_tokens() {
+        let client = client(Tokens::default());
+
+        let (status, body) = get(&client, Some("Bearer "));
+        assert_eq!(status, Status::Unauthorized);
+        assert!(body.contains("not enabled"), "{body}");
+    }
+}
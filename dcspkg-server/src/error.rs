@@ -0,0 +1,162 @@
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::{catch, Request};
+
+/// Everything that can go wrong handling a request.
+/// Each is sent to the client as a JSON body with the matching status code.
+#[derive(Debug)]
+pub enum Error {
+    /// The thing asked for doesn't exist (404)
+    NotFound(String),
+    /// The request was invalid (400)
+    BadRequest(String),
+    /// The request conflicts with what's already there, ie uploading a version that exists (409)
+    Conflict(String),
+    /// The database is busy or can't be reached right now, so the request may work if retried (503)
+    Unavailable(sqlx::Error),
+    /// Anything else, which is a problem with the server (500)
+    Internal(String),
+}
+
+/// The JSON body sent for an error
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ErrorBody {
+    /// The status code, repeated for convenience
+    pub status: u16,
+    /// A description of what went wrong
+    pub error: String,
+}
+
+impl Error {
+    pub fn status(&self) -> Status {
+        match self {
+            Error::NotFound(_) => Status::NotFound,
+            Error::BadRequest(_) => Status::BadRequest,
+            Error::Conflict(_) => Status::Conflict,
+            Error::Unavailable(_) => Status::ServiceUnavailable,
+            Error::Internal(_) => Status::InternalServerError,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+                Error::Unavailable(e)
+            }
+            //sqlite's primary result codes are the bottom byte of the extended ones
+            _ if sqlite_code(&e).map_or(false, |code| {
+                matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED)
+            }) =>
+            {
+                Error::Unavailable(e)
+            }
+            _ => Error::Internal(format!("Database error: {e}")),
+        }
+    }
+}
+
+/// Whether the error is from adding a row that already exists
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(
+        sqlite_code(e),
+        Some(SQLITE_CONSTRAINT_PRIMARYKEY | SQLITE_CONSTRAINT_UNIQUE)
+    )
+}
+
+/// The extended result code of an error from sqlite
+fn sqlite_code(e: &sqlx::Error) -> Option<i32> {
+    match e {
+        sqlx::Error::Database(db_error) => db_error.code()?.parse().ok(),
+        _ => None,
+    }
+}
+
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+const SQLITE_CONSTRAINT_PRIMARYKEY: i32 = 1555;
+const SQLITE_CONSTRAINT_UNIQUE: i32 = 2067;
+
+impl<'r> Responder<'r, 'static> for Error {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+
+        //the details of server side problems are logged, rather than given to the client
+        let error = match self {
+            Error::NotFound(message) | Error::BadRequest(message) | Error::Conflict(message) => {
+                message
+            }
+            Error::Unavailable(e) => {
+                log::warn!(
+                    "{} {}: database unavailable: {e}",
+                    request.method(),
+                    request.uri()
+                );
+                "The package database is unavailable, try again later".to_owned()
+            }
+            Error::Internal(e) => {
+                log::error!("{} {}: {e}", request.method(), request.uri());
+                "Internal server error".to_owned()
+            }
+        };
+
+        (
+            status,
+            Json(ErrorBody {
+                status: status.code,
+                error,
+            }),
+        )
+            .respond_to(request)
+    }
+}
+
+/// A message from a request guard that failed, for the catcher to send instead of the status's reason.
+/// Kept in the request's local cache
+pub struct GuardError(pub Option<String>);
+
+/// Sends JSON for errors that don't come from a handler, ie when no route matches or a guard fails
+#[catch(default)]
+pub fn default_catcher(status: Status, request: &Request) -> (Status, Json<ErrorBody>) {
+    let body = ErrorBody {
+        status: status.code,
+        error: request
+            .local_cache(|| GuardError(None))
+            .0
+            .clone()
+            .unwrap_or_else(|| status.reason_lossy().to_owned()),
+    };
+    (status, Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Connection, SqliteConnection};
+
+    #[rocket::async_test]
+    async fn detects_unique_violations() {
+        let mut db = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE packages (pkgname TEXT, version TEXT, PRIMARY KEY (pkgname, version))",
+        )
+        .execute(&mut db)
+        .await
+        .unwrap();
+
+        let insert = "INSERT INTO packages VALUES ('pkg', '1.0.0')";
+        sqlx::query(insert).execute(&mut db).await.unwrap();
+        let e = sqlx::query(insert).execute(&mut db).await.unwrap_err();
+        assert!(is_unique_violation(&e), "{e}");
+
+        let e = sqlx::query("SELECT * FROM missing")
+            .execute(&mut db)
+            .await
+            .unwrap_err();
+        assert!(!is_unique_violation(&e));
+    }
+}
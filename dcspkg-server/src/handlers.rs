@@ -1,21 +1,130 @@
-use crate::db::{get_all_packages, get_package_by_name};
+use crate::error::Error;
+use dcspkg::db::{ListOrder, PackageStore};
 use dcspkg::Package;
-use rocket::serde::json::Json;
-use rocket::{get, State};
-
-#[get("/list")]
-pub async fn list(db: &State<sqlx::SqlitePool>) -> Json<Vec<Package>> {
-    match get_all_packages(db.inner()).await {
-        Ok(x) => Json(x),
-        Err(e) => panic!("{e:?}"), //TODO, work out how to handle failure in reponder
+use dcspkg::TOTAL_COUNT_HEADER;
+use rocket::form::{error::ErrorKind, Errors};
+use rocket::http::Header;
+use rocket::serde::json::{serde_json, Json, Value};
+use rocket::{get, FromFormField, Responder, State};
+
+/// How to sort the package list
+#[derive(FromFormField, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sort {
+    /// Alphabetically by pkgname
+    Name,
+    /// Most recently updated first
+    Updated,
+    /// Largest first
+    Size,
+}
+
+/// A page of the package list, along with the total number of packages so clients can page through it
+#[derive(Responder)]
+pub struct PackageList {
+    packages: Json<Vec<Value>>,
+    total: Header<'static>,
+}
+
+#[get("/list?<tag>&<sort>&<limit>&<offset>&<fields>")]
+pub async fn list(
+    db: &State<PackageStore>,
+    tag: Option<&str>,
+    sort: Result<Sort, Errors<'_>>,
+    limit: Result<u64, Errors<'_>>,
+    offset: Result<u64, Errors<'_>>,
+    fields: Option<&str>,
+) -> Result<PackageList, Error> {
+    let sort = optional("sort", sort)?;
+    let limit = optional("limit", limit)?;
+    let offset = optional("offset", offset)?;
+
+    let order = match sort.unwrap_or(Sort::Name) {
+        Sort::Name => ListOrder::Name,
+        Sort::Updated => ListOrder::Updated,
+        Sort::Size => ListOrder::Size,
+    };
+    let page = db.list(tag, order, limit, offset.unwrap_or(0)).await?;
+    let total = db.count(tag).await?;
+
+    Ok(PackageList {
+        packages: Json(select_fields(page.into_iter(), fields)?),
+        total: Header::new(TOTAL_COUNT_HEADER, total.to_string()),
+    })
+}
+
+/// Rocket quietly turns invalid values for `Option` query parameters into `None`,
+/// so those are taken as a `Result` and told apart from missing ones here
+fn optional<T>(name: &str, param: Result<T, Errors<'_>>) -> Result<Option<T>, Error> {
+    match param {
+        Ok(value) => Ok(Some(value)),
+        Err(errors) if errors.iter().all(|e| matches!(e.kind, ErrorKind::Missing)) => Ok(None),
+        Err(errors) => {
+            let reasons: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            Err(Error::BadRequest(format!(
+                "Invalid {name}: {}",
+                reasons.join(", ")
+            )))
+        }
     }
 }
 
+/// Converts packages to JSON, keeping only the comma separated `fields` if given
+fn select_fields(
+    packages: impl Iterator<Item = Package>,
+    fields: Option<&str>,
+) -> Result<Vec<Value>, Error> {
+    let fields: Option<Vec<&str>> = fields.map(|fields| fields.split(',').collect());
+
+    packages
+        .map(|pkg| {
+            let mut value = serde_json::to_value(pkg)
+                .map_err(|e| Error::Internal(format!("Could not serialise package: {e}")))?;
+            if let (Some(fields), Value::Object(map)) = (&fields, &mut value) {
+                if let Some(field) = fields.iter().find(|field| !map.contains_key(**field)) {
+                    return Err(Error::BadRequest(format!("Unknown field {field:?}")));
+                }
+                map.retain(|key, _| fields.contains(&key.as_str()));
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
 #[get("/pkgdata/<name>")]
-pub async fn pkgdata(db: &State<sqlx::SqlitePool>, name: &str) -> Option<Json<Package>> {
-    get_package_by_name(db.inner(), name)
-        .await
-        .ok()
-        .flatten()
+pub async fn pkgdata(db: &State<PackageStore>, name: &str) -> Result<Json<Package>, Error> {
+    db.latest(name)
+        .await?
         .map(Json)
+        .ok_or_else(|| Error::NotFound(format!("Package {name} does not exist")))
+}
+
+#[get("/pkgdata/<name>/<version>")]
+pub async fn pkgdata_version(
+    db: &State<PackageStore>,
+    name: &str,
+    version: &str,
+) -> Result<Json<Package>, Error> {
+    let not_found = || {
+        Error::NotFound(format!(
+            "Package {name} does not exist, or has no version {version}"
+        ))
+    };
+
+    //nothing is stored under a version that isn't valid semver
+    let semver = version.parse().map_err(|_| not_found())?;
+    db.get(name, &semver).await?.map(Json).ok_or_else(not_found)
+}
+
+#[get("/versions/<name>")]
+pub async fn versions(db: &State<PackageStore>, name: &str) -> Result<Json<Vec<Package>>, Error> {
+    let versions = db.versions(name).await?;
+    if versions.is_empty() {
+        return Err(Error::NotFound(format!("Package {name} does not exist")));
+    }
+    Ok(Json(versions))
+}
+
+#[get("/search?<q>")]
+pub async fn search(db: &State<PackageStore>, q: &str) -> Result<Json<Vec<Package>>, Error> {
+    Ok(Json(db.search(q).await?))
 }
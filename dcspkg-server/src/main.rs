@@ -1,7 +1,13 @@
-use handlers::*;
-use rocket::routes;
+use admin::PackageDir;
+use anyhow::Context;
+use auth::Tokens;
+use dcspkg::db::PackageStore;
+use rocket::data::ByteUnit;
+use rocket::{catchers, routes};
 
-mod db;
+mod admin;
+mod auth;
+mod error;
 mod handlers;
 
 #[rocket::main]
@@ -9,16 +15,43 @@ async fn main() -> anyhow::Result<()> {
     let package_path =
         std::env::var("PACKAGE_PATH").unwrap_or_else(|_| "./packages/packages".to_owned());
 
-    let db = {
-        let path =
-            std::env::var("DB_PATH").unwrap_or_else(|_| "./packages/packagedb.sqlite".to_owned());
-        sqlx::SqlitePool::connect(&path).await?
-    };
+    let db_path =
+        std::env::var("DB_PATH").unwrap_or_else(|_| "./packages/packagedb.sqlite".to_owned());
+    let db = PackageStore::open(&db_path).await?;
 
-    rocket::build()
+    let tokens = Tokens::from_env()?;
+
+    //uploads through the admin API are limited to this size, ie "8GiB"
+    let max_upload: ByteUnit = std::env::var("MAX_UPLOAD_SIZE")
+        .unwrap_or_else(|_| "4GiB".to_owned())
+        .parse()
+        .ok()
+        .context("Invalid MAX_UPLOAD_SIZE")?;
+    let config = rocket::Config::figment()
+        .merge(("limits.file", max_upload))
+        .merge(("limits.data-form", max_upload));
+
+    rocket::custom(config)
         .manage(db)
-        .mount("/", routes![list, pkgdata])
+        .manage(tokens)
+        .manage(PackageDir(package_path.clone().into()))
+        .mount(
+            "/",
+            routes![
+                handlers::list,
+                handlers::pkgdata,
+                handlers::pkgdata_version,
+                handlers::versions,
+                handlers::search,
+                admin::upload,
+                admin::update,
+                admin::remove,
+                admin::yank,
+                admin::unyank
+            ],
+        )
         .mount("/download", rocket::fs::FileServer::from(package_path))
+        .register("/", catchers![error::default_catcher])
         .launch()
         .await
         .map(|_| ())
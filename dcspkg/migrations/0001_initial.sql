@@ -0,0 +1,10 @@
+-- The original schema, from before migrations were tracked
+CREATE TABLE IF NOT EXISTS packages(
+    pkgname STRING PRIMARY KEY NOT NULL,
+    fullname STRING NOT NULL,
+    description STRING,
+    image_url STRING,
+    executable_path STRING,
+    crc INTEGER NOT NULL,
+    has_installer INTEGER NOT NULL,
+    add_to_path INTEGER NOT NULL);
@@ -0,0 +1,20 @@
+-- Packages are keyed on (pkgname, version)
+-- Existing packages become version 0.0.0
+CREATE TABLE packages_new(
+    pkgname STRING NOT NULL,
+    version STRING NOT NULL,
+    fullname STRING NOT NULL,
+    description STRING,
+    image_url STRING,
+    executable_path STRING,
+    crc INTEGER NOT NULL,
+    has_installer INTEGER NOT NULL,
+    add_to_path INTEGER NOT NULL,
+    PRIMARY KEY (pkgname, version));
+
+INSERT INTO packages_new
+    SELECT pkgname, '0.0.0', fullname, description, image_url, executable_path, crc, has_installer, add_to_path
+    FROM packages;
+
+DROP TABLE packages;
+ALTER TABLE packages_new RENAME TO packages;
@@ -0,0 +1,3 @@
+-- SHA-256 digests of package archives
+-- Existing packages have none, and are checked using their CRC
+ALTER TABLE packages ADD COLUMN sha256 STRING;
@@ -0,0 +1,2 @@
+-- ed25519 signatures of package archives
+ALTER TABLE packages ADD COLUMN signature STRING;
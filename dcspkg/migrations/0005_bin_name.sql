@@ -0,0 +1,2 @@
+-- names to add executables to path as, when not their file name
+ALTER TABLE packages ADD COLUMN bin_name STRING;
@@ -0,0 +1,2 @@
+-- extra named executables, as a JSON array of {name, path, add_to_path} objects
+ALTER TABLE packages ADD COLUMN entry_points STRING;
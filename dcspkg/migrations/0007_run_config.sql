@@ -0,0 +1,2 @@
+-- how to launch packages, as a JSON object with cwd, env and args fields
+ALTER TABLE packages ADD COLUMN run_config STRING;
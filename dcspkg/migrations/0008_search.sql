@@ -0,0 +1,21 @@
+-- full text search over package names and descriptions
+-- kept up to date with the packages table by triggers, which only fire when an indexed column changes
+CREATE VIRTUAL TABLE packages_fts USING fts5(pkgname, version UNINDEXED, fullname, description);
+
+INSERT INTO packages_fts (pkgname, version, fullname, description)
+    SELECT pkgname, version, fullname, description FROM packages;
+
+CREATE TRIGGER packages_fts_insert AFTER INSERT ON packages BEGIN
+    INSERT INTO packages_fts (pkgname, version, fullname, description)
+        VALUES (new.pkgname, new.version, new.fullname, new.description);
+END;
+
+CREATE TRIGGER packages_fts_delete AFTER DELETE ON packages BEGIN
+    DELETE FROM packages_fts WHERE pkgname = old.pkgname AND version = old.version;
+END;
+
+CREATE TRIGGER packages_fts_update AFTER UPDATE OF pkgname, version, fullname, description ON packages BEGIN
+    DELETE FROM packages_fts WHERE pkgname = old.pkgname AND version = old.version;
+    INSERT INTO packages_fts (pkgname, version, fullname, description)
+        VALUES (new.pkgname, new.version, new.fullname, new.description);
+END;
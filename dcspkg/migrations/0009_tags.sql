@@ -0,0 +1,5 @@
+-- tags/categories for packages, shared by all versions of a package
+CREATE TABLE package_tags(
+    pkgname STRING NOT NULL,
+    tag STRING NOT NULL,
+    PRIMARY KEY (pkgname, tag));
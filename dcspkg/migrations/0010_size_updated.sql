@@ -0,0 +1,4 @@
+-- archive sizes in bytes, and when each version was added in seconds since the unix epoch
+-- both are unknown for existing packages
+ALTER TABLE packages ADD COLUMN size INTEGER;
+ALTER TABLE packages ADD COLUMN updated INTEGER;
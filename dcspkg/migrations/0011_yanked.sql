@@ -0,0 +1,2 @@
+-- yanked versions are hidden from the package list and never picked as the latest
+ALTER TABLE packages ADD COLUMN yanked INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- marks the latest version of each package that hasn't been yanked, so the package list can be
+-- filtered, sorted and paged in sql. Semver ordering can't be done in sql, so this is kept up to date
+-- by PackageStore whenever a package's versions change. It's null until it has been worked out
+ALTER TABLE packages ADD COLUMN latest INTEGER;
+CREATE INDEX packages_latest ON packages (latest, pkgname);
+CREATE INDEX package_tags_tag ON package_tags (tag, pkgname);
@@ -1,6 +1,9 @@
 use crate::config::DcspkgConfig;
 use crate::util::*;
-use crate::{install_package, list_all_packages, run_package};
+use crate::{
+    get_package_info, install_package, list_all_packages, list_upgrades, run_package,
+    search_packages, uninstall_package, upgrade_package, InstallMode, InstallOptions,
+};
 use clap::{Parser, Subcommand};
 
 //clap stuff
@@ -23,51 +26,196 @@ pub enum Command {
     List {
         #[clap(long, short, action)]
         json: bool,
+        /// Only list packages with this tag, ie "games"
+        #[clap(long, short)]
+        tag: Option<String>,
+        /// How to sort the list
+        #[clap(long, short, value_parser = ["name", "updated", "size"])]
+        sort: Option<String>,
+    },
+    /// Search for packages by name and description
+    Search {
+        #[clap(required = true)]
+        terms: Vec<String>,
+        #[clap(long, short, action)]
+        json: bool,
     },
     /// Install a package
-    Install { package: String },
+    Install {
+        package: String,
+        /// Install the package even if it is not signed by a trusted key
+        #[clap(long, action)]
+        allow_untrusted: bool,
+        /// Replace the package if it is already installed
+        #[clap(long, action, conflicts_with = "force")]
+        reinstall: bool,
+        /// Overwrite anything in the way of the install, even if it was not installed by dcspkg
+        #[clap(long, action)]
+        force: bool,
+        /// Add the package's executable to path under this name instead
+        #[clap(long = "as", value_name = "NAME")]
+        alias: Option<String>,
+    },
+    /// Uninstall a package
+    Uninstall { package: String },
+    /// Upgrade installed packages to the latest version available
+    Upgrade {
+        /// Only upgrade this package
+        package: Option<String>,
+        /// Show what would be upgraded, without changing anything
+        #[clap(long, action)]
+        dry_run: bool,
+        /// Upgrade packages even if they are not signed by a trusted key
+        #[clap(long, action)]
+        allow_untrusted: bool,
+    },
     ///Show all installed packages and their versions
     Installed {
         #[clap(long, short, action)]
         json: bool,
     },
-    ///Run the executable from the package specified
-    Run { package: String },
+    /// Show everything about a package, on the server and as installed
+    Info {
+        package: String,
+        #[clap(long, short, action)]
+        json: bool,
+    },
+    ///Run the executable from the package specified, or another entry point with <package>:<entry>
+    Run {
+        package: String,
+        /// Arguments to pass to the executable, after --
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
 }
 
 //where the cli opts are dispatched to functions
 impl Command {
-    pub fn run(&self, config: DcspkgConfig) -> anyhow::Result<()> {
+    pub fn run(&self, mut config: DcspkgConfig) -> anyhow::Result<()> {
         use Command::*;
         match &self {
             //list all the packages to stdout
-            List { json } => {
-                let packages = list_all_packages(config.server.url)?;
+            List { json, tag, sort } => {
+                let packages =
+                    list_all_packages(config.server.url, tag.as_deref(), sort.as_deref())?;
                 print_package_list(&packages, *json);
                 Ok(())
             }
 
+            //search for packages, best match first
+            Search { terms, json } => {
+                let packages = search_packages(&terms.join(" "), config.server.url)?;
+                if packages.is_empty() && !*json {
+                    println!("No packages found");
+                } else {
+                    print_package_list(&packages, *json);
+                }
+                Ok(())
+            }
+
             //install a package
-            Install { package } => install_package(
+            Install {
+                package,
+                allow_untrusted,
+                reinstall,
+                force,
+                alias,
+            } => {
+                config.trust.allow_untrusted |= allow_untrusted;
+                let mode = if *force {
+                    InstallMode::Force
+                } else if *reinstall {
+                    InstallMode::Reinstall
+                } else {
+                    InstallMode::Normal
+                };
+                install_package(
+                    package,
+                    config.server.url,
+                    config.registry.install_dir,
+                    config.registry.bin_dir,
+                    config.registry.registry_file,
+                    &config.trust,
+                    &InstallOptions {
+                        mode,
+                        alias: alias.clone(),
+                        limits: config.limits,
+                    },
+                )
+            }
+
+            //uninstall a package
+            Uninstall { package } => uninstall_package(
                 package,
-                config.server.url,
                 config.registry.install_dir,
                 config.registry.bin_dir,
                 config.registry.registry_file,
             ),
 
+            //upgrade installed packages
+            Upgrade {
+                package,
+                dry_run,
+                allow_untrusted,
+            } => {
+                config.trust.allow_untrusted |= allow_untrusted;
+                let upgrades = list_upgrades(
+                    package.as_deref(),
+                    config.server.url.as_str(),
+                    &config.registry.registry_file,
+                )?;
+                print_upgrade_list(&upgrades);
+
+                if *dry_run {
+                    return Ok(());
+                }
+
+                for upgrade in &upgrades {
+                    upgrade_package(
+                        upgrade,
+                        config.server.url.as_str(),
+                        &config.registry.install_dir,
+                        &config.registry.bin_dir,
+                        &config.registry.registry_file,
+                        &config.trust,
+                        &config.limits,
+                    )?;
+                    println!(
+                        "Upgraded {} to version {}",
+                        upgrade.available.pkgname, upgrade.available.version
+                    );
+                }
+                Ok(())
+            }
+
             //list what we have installed
             Installed { json } => {
-                let packages = list_installed_packages(&config.registry.registry_file)?;
+                let packages: Vec<_> = list_installed_packages(&config.registry.registry_file)?
+                    .into_iter()
+                    .map(|installed| installed.package)
+                    .collect();
                 print_package_list(&packages, *json);
                 Ok(())
             }
 
+            //show the details of a package
+            Info { package, json } => {
+                let info = get_package_info(
+                    package,
+                    config.server.url,
+                    &config.registry.install_dir,
+                    &config.registry.registry_file,
+                )?;
+                print_package_info(&info, *json);
+                Ok(())
+            }
+
             //run an executable from a package
-            Run { package } => run_package(
+            Run { package, args } => run_package(
                 &config.registry.registry_file,
                 config.registry.install_dir,
                 package,
+                args,
             ),
         }
     }
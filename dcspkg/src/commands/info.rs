@@ -0,0 +1,102 @@
+use super::install::get_pkg_data;
+use crate::util::list_installed_packages;
+use crate::{InstalledPackage, Package};
+use anyhow::{bail, Context, Result};
+use reqwest::IntoUrl;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Everything known about a package, from both the server and the local registry.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct PackageInfo {
+    /// The latest version of the package on the server, if the server has it
+    pub remote: Option<Package>,
+    /// The package as it is installed locally, if it is
+    pub installed: Option<InstalledInfo>,
+    /// Why the server could not be checked, if it couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_error: Option<String>,
+}
+
+/// Details of a locally installed package.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct InstalledInfo {
+    #[serde(flatten)]
+    pub entry: InstalledPackage,
+    /// The directory the package is installed in
+    pub location: PathBuf,
+    /// The total size of the package's files, in bytes
+    pub size: u64,
+}
+
+/// Gets the details of a package from the server and the local registry.
+/// If the package is installed, it is still shown if the server can't be reached.
+pub fn get_package_info<P: AsRef<Path>>(
+    pkg_name: &str,           //the packages pkgname
+    server_url: impl IntoUrl, //the url of the server, from config
+    package_dir: P,           //the local package install dir, from config
+    registry_file: P,         //the local json registry file, from config
+) -> Result<PackageInfo> {
+    let installed = list_installed_packages(registry_file.as_ref())?
+        .into_iter()
+        .find(|installed| installed.package.pkgname == pkg_name)
+        .map(|entry| {
+            let location = package_dir.as_ref().join(pkg_name);
+            let size = package_size(&entry, &location);
+            InstalledInfo {
+                entry,
+                location,
+                size,
+            }
+        });
+
+    let remote = server_url
+        .into_url()
+        .context("Could not parse server URL")
+        .and_then(|url| get_pkg_data(pkg_name, &url))
+        .context("Could not get package data from server");
+
+    let (remote, server_error) = match (remote, &installed) {
+        (Ok(remote), _) => (remote, None),
+        (Err(e), Some(_)) => {
+            log::warn!("{e:?}");
+            (None, Some(e.to_string()))
+        }
+        (Err(e), None) => return Err(e),
+    };
+
+    if remote.is_none() && installed.is_none() {
+        bail!("Package {pkg_name} does not exist on the server and is not installed");
+    }
+
+    Ok(PackageInfo {
+        remote,
+        installed,
+        server_error,
+    })
+}
+
+/// The total size of an installed package's files.
+/// Registries from older versions have no manifest, so the install directory is measured instead
+fn package_size(entry: &InstalledPackage, location: &Path) -> u64 {
+    if entry.manifest.files.is_empty() {
+        dir_size(location)
+    } else {
+        entry.manifest.files.iter().map(|file| file.size).sum()
+    }
+}
+
+/// Adds up the sizes of all the files within `dir`, without following symlinks
+fn dir_size(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => dir_size(&entry.path()),
+            Ok(kind) if kind.is_file() => entry.metadata().map_or(0, |meta| meta.len()),
+            _ => 0,
+        })
+        .sum()
+}
@@ -1,31 +1,71 @@
-use crate::Package;
+use super::launcher::write_launcher;
+use super::uninstall::{check_pkg_name, installed_links, is_link_to, owns_link};
+use super::unpack::unpack_archive;
+use crate::config::{Limits, Trust};
+use crate::manifest::{refresh_files, Manifest, ManifestFile};
+use crate::util::LockedRegistry;
+use crate::{InstalledPackage, Package};
 use anyhow::{anyhow, bail, Context, Result};
-use bytes::{Buf, BufMut, BytesMut};
+use ed25519_dalek::{Signature, VerifyingKey};
 use flate2::{read::GzDecoder, CrcReader};
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 use reqwest::blocking::get;
 use reqwest::get as async_get;
 use reqwest::{StatusCode, Url};
+use semver::{Op, VersionReq};
+use sha2::{Digest, Sha256};
 use std::cmp::min;
 use std::fmt::Write;
 use std::os::unix::fs::{symlink, PermissionsExt};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
 use std::{
     fs::{self, Permissions},
-    io::Seek,
+    io,
 };
-use tar::Archive;
+use tempfile::TempDir;
 use tokio::runtime;
 
+/// What to do when installing a package that is already installed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InstallMode {
+    /// Do nothing if the same package is already installed, and fail if a different one is
+    #[default]
+    Normal,
+    /// Cleanly replace an existing installation of the package
+    Reinstall,
+    /// Overwrite anything in the way, even if it was not installed by dcspkg
+    Force,
+}
+
+/// Options for installing a package.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstallOptions {
+    /// What to do if the package is already installed
+    pub mode: InstallMode,
+    /// The name to add the package's executable to path as, overriding the package's own
+    pub alias: Option<String>,
+    /// Limits on what the package may do, from config
+    pub limits: Limits,
+}
+
 /// Installs the specified package locally.
+/// The package may be given as `name@requirement`, ie `gcc@1.2` or `gcc@^1`,
+/// to install the newest version matching a semver requirement.
+/// Otherwise, the latest version is installed.
+/// Packages must be signed by one of the keys in `trust`, unless it allows untrusted packages.
+/// The executable is added to path under `options.alias` if given, and the install is refused
+/// before anything is downloaded if that name is already taken in `bin_dir`.
+/// The registry is locked for the whole install, so nothing else can install or remove the package meanwhile.
 pub fn install_package<P: AsRef<Path>>(
-    pkg_name: &str,                    //the packages pkgname
+    pkg_spec: &str, //the packages pkgname, with an optional version requirement
     server_url: impl reqwest::IntoUrl, //the url of the server, from config
-    package_dir: P,                    //the local package install dir, from config
-    bin_dir: P,                        //the local bin install dir, from config
-    registry_file: P,                  //the local json registry file, from config
+    package_dir: P, //the local package install dir, from config
+    bin_dir: P,     //the local bin install dir, from config
+    registry_file: P, //the local json registry file, from config
+    trust: &Trust,  //the trusted signing keys, from config
+    options: &InstallOptions, //how to install the package
 ) -> Result<()> {
     let server_url = server_url
         .into_url()
@@ -33,53 +73,434 @@ pub fn install_package<P: AsRef<Path>>(
 
     let package_dir = package_dir.as_ref();
     let bin_dir = bin_dir.as_ref();
+    let registry_file = registry_file.as_ref();
+
+    let (pkg_name, version_req) = parse_pkg_spec(pkg_spec)?;
+    check_pkg_name(pkg_name).context("Refusing to install package")?;
 
     //get package data
-    let pkg =
-        get_pkg_data(pkg_name, &server_url).context("Could not get package data from server")?;
+    let pkg = match &version_req {
+        Some(req) => resolve_pkg_version(pkg_name, req, &server_url),
+        //if option empty then err here
+        None => get_pkg_data(pkg_name, &server_url)
+            .and_then(|pkg| pkg.context("Package does not exist on server (404)")),
+    }
+    .context("Could not get package data from server")?;
+    //the package is installed and registered under the name asked for
+    if pkg.pkgname != pkg_name {
+        bail!(
+            "Server gave package {:?} when asked for {pkg_name:?}",
+            pkg.pkgname
+        );
+    }
 
-    //create the install directory
-    fs::create_dir_all(package_dir).context("Could not create install directory for package")?;
+    let mut registry = LockedRegistry::open(registry_file)?;
+    install_resolved(
+        pkg,
+        &server_url,
+        package_dir,
+        bin_dir,
+        &mut registry,
+        trust,
+        options,
+    )
+}
 
+/// Installs a package that has already been fetched from the server, as `options` says.
+/// This is shared with upgrades, which are reinstalls of a newer version.
+/// `registry` is held locked throughout, so what is installed can't change under it.
+pub(super) fn install_resolved(
+    pkg: Package,
+    server_url: &Url,
+    package_dir: &Path,
+    bin_dir: &Path,
+    registry: &mut LockedRegistry,
+    trust: &Trust,
+    options: &InstallOptions,
+) -> Result<()> {
+    let pkg_name = pkg.pkgname.as_str();
+    let mode = options.mode;
     let install_dir = package_dir.join(pkg_name);
-    //download, checksum, and decompress into PKGDIR/bin
-    download_install_file(pkg_name, pkg.crc, &server_url, &install_dir)
+    let existing = registry
+        .packages
+        .iter()
+        .find(|installed| installed.package.pkgname == pkg_name);
+
+    match (existing, mode) {
+        (Some(installed), InstallMode::Normal) if same_contents(&installed.package, &pkg) => {
+            log::info!(
+                "Package {pkg_name} version {} is already installed, nothing to do",
+                pkg.version
+            );
+            return Ok(());
+        }
+        (Some(installed), InstallMode::Normal) => bail!(
+            "Package {pkg_name} version {} is already installed, use `dcspkg upgrade` or --reinstall to replace it",
+            installed.package.version
+        ),
+        (None, InstallMode::Normal | InstallMode::Reinstall) if install_dir.exists() => bail!(
+            "Package directory {install_dir:?} already exists but was not installed by dcspkg, use --force to overwrite it"
+        ),
+        _ => (),
+    }
+
+    if mode != InstallMode::Force {
+        let alias = options.alias.as_deref();
+        check_bin_conflict(&pkg, alias, package_dir, bin_dir, &registry.packages)?;
+    }
+
+    //the package is set up in a staging directory,
+    //and only moved into place once everything else has succeeded
+    let (staging, files) = stage_package(&pkg, server_url, package_dir, trust, &options.limits)?;
+
+    replace_installed(
+        pkg,
+        staging.path(),
+        files,
+        package_dir,
+        bin_dir,
+        registry,
+        options,
+    )
+}
+
+/// Moves a staged package into place, adds it to path, and records it in the registry.
+/// Whatever it replaces, the installed version of the package and its links, along with anything
+/// else in the way when forced, is moved aside first and put back if any of that fails.
+fn replace_installed(
+    pkg: Package,
+    staging: &Path,
+    files: Vec<ManifestFile>,
+    package_dir: &Path,
+    bin_dir: &Path,
+    registry: &mut LockedRegistry,
+    options: &InstallOptions,
+) -> Result<()> {
+    let pkg_name = pkg.pkgname.clone();
+    let mode = options.mode;
+    let alias = options.alias.as_deref();
+    let install_dir = package_dir.join(&pkg_name);
+    let existing = registry
+        .packages
+        .iter()
+        .find(|installed| installed.package.pkgname == pkg_name);
+
+    //the new version is ready, so move the old one out of the way.
+    //it's only deleted once the new one is in place, so it can be put back if anything fails
+    let old = tempfile::Builder::new()
+        .prefix(".old-")
+        .tempdir_in(package_dir)
+        .context("Could not create directory for old version")?;
+    let old_dir = old.path().join(&pkg_name);
+
+    let mut moved_links = MovedLinks::new(bin_dir);
+    if let Some(installed) = existing {
+        log::info!("Replacing existing installation of {pkg_name}");
+        let links = installed_links(installed, &install_dir, bin_dir)?;
+        if let Err(e) = moved_links.move_owned(&links, &install_dir) {
+            moved_links.restore();
+            return Err(e).context("Could not move old symlinks out of the way");
+        }
+    }
+    if mode == InstallMode::Force {
+        let in_the_way: Vec<PathBuf> = path_entries(&pkg, alias)?
+            .into_iter()
+            .map(|(name, _)| bin_dir.join(name))
+            .collect();
+        if let Err(e) = moved_links.move_all(&in_the_way) {
+            moved_links.restore();
+            return Err(e).context("Could not move existing files in bin directory out of the way");
+        }
+    }
+    if install_dir.exists() {
+        if mode == InstallMode::Force && existing.is_none() {
+            log::warn!("Overwriting {install_dir:?}");
+        }
+        if let Err(e) = fs::rename(&install_dir, &old_dir) {
+            moved_links.restore();
+            return Err(e).context("Could not move existing package directory out of the way");
+        }
+    }
+
+    //from here on, anything done outside the staging directory has to be undone on failure
+    let result = link_executable(&pkg, alias, &install_dir, bin_dir).and_then(|links| {
+        let result = fs::rename(staging, &install_dir)
+            .context("Could not move package into place")
+            .and_then(|_| {
+                log::info!("Moved package into {install_dir:?}");
+                let entry = InstalledPackage {
+                    package: pkg,
+                    manifest: Manifest {
+                        files,
+                        links: links.clone(),
+                    },
+                    alias: alias.map(str::to_owned),
+                };
+                add_to_registry(registry, entry).context("Could not add package to registry")
+            });
+        if result.is_err() {
+            remove_links(&links);
+        }
+        result
+    });
+
+    //put back whatever was replaced
+    if result.is_err() {
+        moved_links.restore();
+        let _ = fs::remove_dir_all(&install_dir);
+        if old_dir.exists() {
+            log::warn!("Install failed, restoring old version");
+            fs::rename(&old_dir, &install_dir).context("Could not restore old version")?;
+        }
+    }
+
+    result
+}
+
+/// Downloads the package and unpacks it into a new staging directory within `package_dir`,
+/// running its install script if it has one.
+/// The staging directory is deleted when dropped, so nothing is left behind if the install fails.
+fn stage_package(
+    pkg: &Package,
+    server_url: &Url,
+    package_dir: &Path,
+    trust: &Trust,
+    limits: &Limits,
+) -> Result<(TempDir, Vec<ManifestFile>)> {
+    //the paths are joined onto the install directory, so they mustn't be able to leave it
+    pkg.check_paths()?;
+
+    fs::create_dir_all(package_dir).context("Could not create install directory for package")?;
+    let staging = tempfile::Builder::new()
+        .prefix(".staging-")
+        .tempdir_in(package_dir)
+        .context("Could not create staging directory")?;
+    log::info!("Staging package in {:?}", staging.path());
+
+    //download, checksum, and decompress into the staging directory
+    let mut files = download_install_file(pkg, server_url, staging.path(), trust, limits)
         .context("Could not install file")?;
 
     //run install.sh if exists
     if pkg.has_installer {
-        run_install_script(&install_dir).context("Could not run install script for file")?;
+        run_install_script(staging.path()).context("Could not run install script for file")?;
+        //the script removes itself, and may have changed what was unpacked
+        files = refresh_files(staging.path(), files)
+            .context("Could not record files after running install script")?;
     }
 
+    Ok((staging, files))
+}
+
+/// Removes symlinks created by `link_executable`, when rolling back a failed install
+fn remove_links(links: &[PathBuf]) {
+    for link in links {
+        log::info!("Removing symlink {link:?}");
+        if let Err(e) = fs::remove_file(link) {
+            log::warn!("Could not remove symlink {link:?}: {e}");
+        }
+    }
+}
+
+/// Links moved out of the bin directory while they are being replaced,
+/// so they can be put back if installing what replaces them fails.
+/// They are deleted when this is dropped without being restored.
+struct MovedLinks<'a> {
+    bin_dir: &'a Path,
+    dir: Option<TempDir>,
+    moved: Vec<(PathBuf, PathBuf)>,
+}
+
+impl<'a> MovedLinks<'a> {
+    fn new(bin_dir: &'a Path) -> Self {
+        MovedLinks {
+            bin_dir,
+            dir: None,
+            moved: vec![],
+        }
+    }
+
+    /// Moves each of `links` that belongs to the package in `install_dir` out of the way
+    fn move_owned(&mut self, links: &[PathBuf], install_dir: &Path) -> Result<()> {
+        let owned: Vec<PathBuf> = links
+            .iter()
+            .filter(|link| is_link_to(self.bin_dir, link, install_dir))
+            .cloned()
+            .collect();
+        self.move_all(&owned)
+    }
+
+    /// Moves anything that exists at each of `paths` out of the way
+    fn move_all(&mut self, paths: &[PathBuf]) -> Result<()> {
+        for path in paths {
+            if path.symlink_metadata().is_err() {
+                continue;
+            }
+
+            let dir = match &self.dir {
+                Some(dir) => dir,
+                //a dotfile, so it isn't mistaken for something on path
+                None => self.dir.insert(
+                    tempfile::Builder::new()
+                        .prefix(".old-links-")
+                        .tempdir_in(self.bin_dir)
+                        .context("Could not create directory for old links")?,
+                ),
+            };
+            let moved_path = dir.path().join(self.moved.len().to_string());
+
+            log::info!("Moving {path:?} out of the way");
+            fs::rename(path, &moved_path).context(format!("Could not move {path:?}"))?;
+            self.moved.push((path.clone(), moved_path));
+        }
+        Ok(())
+    }
+
+    /// Puts everything back where it was, replacing anything since created there
+    fn restore(self) {
+        for (path, moved_path) in &self.moved {
+            log::info!("Restoring {path:?}");
+            if let Err(e) = fs::rename(moved_path, path) {
+                log::warn!("Could not restore {path:?}: {e}");
+            }
+        }
+    }
+}
+
+/// Whether two packages have the same version and contents
+pub(super) fn same_contents(a: &Package, b: &Package) -> bool {
+    a.version == b.version
+        && match (&a.sha256, &b.sha256) {
+            (Some(a), Some(b)) => a == b,
+            _ => a.crc == b.crc,
+        }
+}
+
+/// The executables to add to path for a package, as pairs of the name to link them as
+/// and their path within the package.
+/// The default executable is named `alias` if given, then the package's `bin_name`,
+/// then the executable's file name. Other entry points are named after themselves.
+fn path_entries<'a>(pkg: &'a Package, alias: Option<&str>) -> Result<Vec<(String, &'a str)>> {
+    let mut entries = vec![];
+
     if pkg.add_to_path {
-        //the relative path from within the package
-        let relative_exe_path: &Path = pkg.executable_path.as_ref().context("Package is configured to add executable to path, but is not configured with an executable path")?.as_ref();
+        let exe_path = pkg.executable_path.as_deref().context(
+            "Package is configured to add executable to path, but is not configured with an executable path",
+        )?;
+        let name = match alias.or(pkg.bin_name.as_deref()) {
+            Some(name) => name.to_owned(),
+            None => Path::new(exe_path)
+                .file_name()
+                .context("Could not get file name from executable path")?
+                .to_string_lossy()
+                .into_owned(),
+        };
+        entries.push((name, exe_path));
+    }
 
+    for entry in pkg.entry_points.iter().filter(|entry| entry.add_to_path) {
+        entries.push((entry.name.clone(), entry.path.as_str()));
+    }
+
+    //make sure the names can't be used to escape the bin directory
+    for (name, _) in &entries {
+        let mut components = Path::new(name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            bail!("Invalid name {name:?} for executable on path");
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Checks that the names the package's executables will be added to path as aren't already taken
+/// in `bin_dir`, reporting which installed package owns them if so.
+/// Anything owned by the package itself is fine, as it's removed before linking.
+fn check_bin_conflict(
+    pkg: &Package,
+    alias: Option<&str>,
+    package_dir: &Path,
+    bin_dir: &Path,
+    registry: &[InstalledPackage],
+) -> Result<()> {
+    for (name, _) in path_entries(pkg, alias)? {
+        let link = bin_dir.join(&name);
+        if link.symlink_metadata().is_err() {
+            continue;
+        }
+
+        let owner = registry.iter().find(|installed| {
+            let install_dir = package_dir.join(&installed.package.pkgname);
+            owns_link(installed, &install_dir, bin_dir, &link)
+        });
+
+        match owner {
+            Some(owner) if owner.package.pkgname == pkg.pkgname => (),
+            Some(owner) => bail!(
+                "{name:?} is already on path from package {}, use --as to install {} under a different name",
+                owner.package.pkgname,
+                pkg.pkgname
+            ),
+            None => bail!(
+                "{link:?} already exists but was not installed by dcspkg, use --as to install {} under a different name, or --force to overwrite it",
+                pkg.pkgname
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Symlinks the package's executables into `bin_dir`, for those that want to be added to path.
+/// Packages that ask for a launcher get a script that sets up their run config instead.
+/// The links are named as described by `path_entries`.
+/// Returns the symlinks created.
+fn link_executable(
+    pkg: &Package,
+    alias: Option<&str>,
+    install_dir: &Path,
+    bin_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let entries = path_entries(pkg, alias)?;
+    if entries.is_empty() {
+        return Ok(vec![]);
+    }
+
+    //create bin path if not already exists
+    fs::create_dir_all(bin_dir).context("Could not create bin directory")?;
+
+    let mut links = vec![];
+    for (name, relative_exe_path) in entries {
         //the symlink to create in /bin, on path
-        let bin_exe_path = bin_dir.join(
-            relative_exe_path
-                .file_name()
-                .context("Could not get file name from executable path")?,
-        );
+        let bin_exe_path = bin_dir.join(name);
 
         //the symlink target, in /packages/<pkg-name>
         let package_exe_path = install_dir.join(relative_exe_path);
 
-        //create bin path if not already exists
-        fs::create_dir_all(bin_dir).context("Could not create bin directory")?;
-
-        let link: &Path = &bin_exe_path;
-        let source: &Path = &package_exe_path;
-        log::info!("Creating symlink to {package_exe_path:?} at {bin_exe_path:?}");
-        symlink(source, link).context("Could not create symbolic link to package executable")?;
+        let result = if pkg.run.launcher {
+            write_launcher(pkg, &package_exe_path, install_dir, &bin_exe_path)
+        } else {
+            log::info!("Creating symlink to {package_exe_path:?} at {bin_exe_path:?}");
+            symlink(&package_exe_path, &bin_exe_path)
+                .context("Could not create symbolic link to package executable")
+        };
+
+        //don't leave the links for the other executables behind
+        if let Err(e) = result {
+            remove_links(&links);
+            return Err(e);
+        }
+        links.push(bin_exe_path);
     }
 
-    add_to_registry(registry_file.as_ref(), pkg).context("Could not add package to registry")?;
-
-    Ok(())
+    Ok(links)
 }
 
-fn get_pkg_data(pkg_name: &str, server_url: &Url) -> Result<Package> {
+/// Gets the latest version of a package from the server, or `None` if the server doesn't have it
+pub(super) fn get_pkg_data(pkg_name: &str, server_url: &Url) -> Result<Option<Package>> {
     let url = server_url
         .join(format!("{}/{}", crate::DATA_ENDPOINT, pkg_name).as_ref())
         .context("Could not parse URL")?;
@@ -92,7 +513,7 @@ fn get_pkg_data(pkg_name: &str, server_url: &Url) -> Result<Package> {
 
     match response.status() {
         StatusCode::OK => (),
-        StatusCode::NOT_FOUND => bail!("Package does not exist on server (404)"),
+        StatusCode::NOT_FOUND => return Ok(None),
         r => bail!("Response from server was not okay (code {})", r.as_u16()),
     }
 
@@ -100,50 +521,173 @@ fn get_pkg_data(pkg_name: &str, server_url: &Url) -> Result<Package> {
 
     log::debug!("Package data: {package:?}");
 
-    //if option empty then err here
-    Ok(package)
+    Ok(Some(package))
+}
+
+/// Splits a package spec of the form `name[@requirement]`
+fn parse_pkg_spec(pkg_spec: &str) -> Result<(&str, Option<VersionReq>)> {
+    match pkg_spec.split_once('@') {
+        None => Ok((pkg_spec, None)),
+        Some((name, req)) => VersionReq::parse(req)
+            .map(|req| (name, Some(req)))
+            .context(format!("Could not parse version requirement {req:?}")),
+    }
+}
+
+/// Finds the newest version of a package on the server that matches `req`
+fn resolve_pkg_version(pkg_name: &str, req: &VersionReq, server_url: &Url) -> Result<Package> {
+    let url = server_url
+        .join(format!("{}/{}", crate::VERSIONS_ENDPOINT, pkg_name).as_ref())
+        .context("Could not parse URL")?;
+
+    log::info!("Downloading versions of package {pkg_name} from {url}...");
+
+    let response = get(url.as_ref()).context("Request failed")?;
+    log::info!("Got reponse from {url}");
+
+    match response.status() {
+        StatusCode::OK => (),
+        StatusCode::NOT_FOUND => bail!("Package does not exist on server (404)"),
+        r => bail!("Response from server was not okay (code {})", r.as_u16()),
+    }
+
+    let versions: Vec<Package> = response.json().context("Could not parse JSON response")?;
+
+    log::debug!("Available versions: {versions:?}");
+
+    //yanked versions are only installed when asked for exactly, ie `gcc@=1.2.3`
+    let exact = matches!(req.comparators.as_slice(), [c] if c.op == Op::Exact && c.patch.is_some());
+
+    versions
+        .into_iter()
+        .filter(|pkg| req.matches(&pkg.version) && (exact || !pkg.yanked))
+        .max_by(|a, b| a.version.cmp(&b.version))
+        .context(format!(
+            "No version of package {pkg_name} matches requirement {req}"
+        ))
 }
 
 fn download_install_file(
-    pkg_name: &str,
-    checksum: u32,
+    pkg: &Package,
     server_url: &Url,
     install_dir: &Path,
-) -> Result<()> {
+    trust: &Trust,
+    limits: &Limits,
+) -> Result<Vec<ManifestFile>> {
+    let pkg_name = &pkg.pkgname;
     let url = server_url
-        .join(format!("{}/{}.dcspkg", crate::FILE_ENDPOINT, pkg_name).as_ref())
+        .join(format!("{}/{}", crate::FILE_ENDPOINT, pkg.archive_name()).as_ref())
         .context("Could not parse URL")?;
 
-    log::info!("Downloading compressed package {pkg_name} from {url}...");
+    log::info!(
+        "Downloading compressed package {pkg_name} version {} from {url}...",
+        pkg.version
+    );
 
     //download the package
     let response = run_download(pkg_name, &url)?;
     log::info!("Finished downloading package...");
 
-    //the content of the response
-    let mut compressed = BytesMut::with_capacity(response.len());
-    compressed.put(response.as_slice());
+    //check the download before anything is written to disk
+    //older packages only have a CRC
+    match &pkg.sha256 {
+        Some(digest) => verify_digest(&response, digest)?,
+        None => verify_checksum(&response, pkg.crc)?,
+    }
+    verify_signature(pkg, trust)?;
 
     log::info!("Decompressing and unpacking package...");
 
-    //decompress and unarchive the bytes
-    let reader = GzDecoder::new(CrcReader::new(compressed.reader()));
-    let mut archive = Archive::new(reader);
+    //don't leave a partially unpacked package behind
+    let existed = install_dir.exists();
+    let files = unpack_archive(&response, install_dir, limits.max_unpacked_size).map_err(|e| {
+        if !existed {
+            let _ = fs::remove_dir_all(install_dir);
+        }
+        e
+    })?;
 
-    //unpack archive
-    archive
-        .unpack(install_dir)
-        .context("Could not unpack archive")?;
+    log::info!("Unpacked archive");
+    log::debug!("Unpacked {} files into {:?}", files.len(), install_dir);
 
-    let downloaded_checksum = archive.into_inner().into_inner().crc().sum();
-    log::info!("Checksum of downloaded package is {downloaded_checksum} (expected {checksum})");
+    Ok(files)
+}
 
-    // if downloaded_checksum != checksum {
-    //     return Err(anyhow!("Checksum for downloaded file did not match!"));
-    // }
+/// Checks the package is signed by one of the trusted keys.
+/// The signature covers the archive's SHA-256 as well as the metadata, so the digest must already have been checked
+fn verify_signature(pkg: &Package, trust: &Trust) -> Result<()> {
+    let result = match &pkg.signature {
+        Some(signature) => pkg
+            .signed_payload()
+            .and_then(|payload| check_signature(&payload, signature, &trust.keys)),
+        None => Err(anyhow!("Package {} is not signed", pkg.pkgname)),
+    };
+
+    match result {
+        Ok(key) => {
+            log::info!("Package is signed by trusted key {key}");
+            Ok(())
+        }
+        Err(e) if trust.allow_untrusted => {
+            log::warn!("{e:#}, installing anyway as untrusted packages are allowed");
+            Ok(())
+        }
+        Err(e) => Err(e)
+            .context("Refusing to install untrusted package (use --allow-untrusted to override)"),
+    }
+}
 
-    log::info!("Unpacked archive");
-    log::debug!("Unpacked into {:?}", install_dir);
+/// Returns the trusted key that produced `signature`, if there is one
+fn check_signature<'a>(payload: &[u8], signature: &str, keys: &'a [String]) -> Result<&'a str> {
+    let signature: [u8; 64] = hex::decode(signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("Package signature is malformed")?;
+    let signature = Signature::from_bytes(&signature);
+
+    for key in keys {
+        let verifying_key = hex::decode(key)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+
+        match verifying_key {
+            Some(verifying_key) => {
+                if verifying_key.verify_strict(payload, &signature).is_ok() {
+                    return Ok(key);
+                }
+            }
+            None => log::warn!("Trusted key {key} in config is malformed, ignoring it"),
+        }
+    }
+
+    bail!("Package is not signed by a trusted key")
+}
+
+/// Checks the SHA-256 digest of the package archive against the one the server gave us
+fn verify_digest(compressed: &[u8], digest: &str) -> Result<()> {
+    let downloaded_digest = format!("{:x}", Sha256::digest(compressed));
+    log::info!("SHA-256 of downloaded package is {downloaded_digest} (expected {digest})");
+
+    if !downloaded_digest.eq_ignore_ascii_case(digest) {
+        bail!("SHA-256 digest for downloaded package did not match (expected {digest}, got {downloaded_digest}), the download may be corrupt or tampered with");
+    }
+
+    Ok(())
+}
+
+/// Checks the CRC of the decompressed package against the one the server gave us.
+/// The CRC is of the uncompressed tarball, so this has to decompress the whole thing.
+fn verify_checksum(compressed: &[u8], checksum: u32) -> Result<()> {
+    let mut reader = CrcReader::new(GzDecoder::new(compressed));
+    io::copy(&mut reader, &mut io::sink()).context("Could not decompress package")?;
+
+    let downloaded_checksum = reader.crc().sum();
+    log::info!("Checksum of downloaded package is {downloaded_checksum} (expected {checksum})");
+
+    if downloaded_checksum != checksum {
+        bail!("Checksum for downloaded package did not match (expected {checksum}, got {downloaded_checksum}), the download may be corrupt");
+    }
 
     Ok(())
 }
@@ -250,28 +794,150 @@ fn run_install_script(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn add_to_registry(registry_file: &Path, package: Package) -> Result<()> {
-    //create empty registry if not exists
-    if !registry_file.exists() {
-        fs::write(registry_file, "[]").context("Could not create package registry")?;
+fn add_to_registry(registry: &mut LockedRegistry, package: InstalledPackage) -> Result<()> {
+    //there should only ever be one entry per package
+    registry
+        .packages
+        .retain(|installed| installed.package.pkgname != package.package.pkgname);
+    registry.packages.push(package);
+    registry.save()?;
+
+    log::info!("Added package to local registry");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EntryPoint, RunConfig};
+    use semver::Version;
+
+    /// A package named `pkg`, adding `bin/run` to path as `run`, along with any other `entry_points`
+    fn package(version: &str, entry_points: Vec<EntryPoint>) -> Package {
+        Package {
+            pkgname: "pkg".to_string(),
+            version: Version::parse(version).unwrap(),
+            fullname: "Package".to_string(),
+            description: None,
+            image_url: None,
+            executable_path: Some("bin/run".to_string()),
+            crc: 0,
+            sha256: None,
+            signature: None,
+            has_installer: false,
+            add_to_path: true,
+            bin_name: None,
+            entry_points,
+            run: RunConfig::default(),
+            tags: vec![],
+            size: None,
+            updated: None,
+            yanked: false,
+        }
     }
 
-    let mut file = fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(registry_file)
-        .context("Could not open registry file")?;
+    /// Writes a package directory at `dir` whose executable contains `contents`
+    fn write_package(dir: &Path, contents: &str) {
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::write(dir.join("bin/run"), contents).unwrap();
+    }
 
-    let mut loaded: Vec<Package> =
-        serde_json::from_reader(&file).context("Could not deserialize registry file context")?;
-    log::debug!("Deserialised contents of registry file");
+    /// A temporary directory with version 1.0.0 of `pkg` installed in `packages`,
+    /// and its executable linked into `bin`
+    fn installed() -> (TempDir, LockedRegistry) {
+        let dir = tempfile::tempdir().unwrap();
+        let package_dir = dir.path().join("packages");
+        let bin_dir = dir.path().join("bin");
+        write_package(&package_dir.join("pkg"), "old");
+
+        let pkg = package("1.0.0", vec![]);
+        let links = link_executable(&pkg, None, &package_dir.join("pkg"), &bin_dir).unwrap();
+        let mut registry = LockedRegistry::open(&dir.path().join("registry.json")).unwrap();
+        let entry = InstalledPackage {
+            package: pkg,
+            manifest: Manifest {
+                files: vec![],
+                links,
+            },
+            alias: None,
+        };
+        add_to_registry(&mut registry, entry).unwrap();
+
+        (dir, registry)
+    }
 
-    loaded.push(package);
-    file.rewind()
-        .and_then(|_| serde_json::to_writer(&file, &loaded).map_err(Into::into))
-        .context("Could not write registry back to file")?;
+    fn replace(dir: &TempDir, registry: &mut LockedRegistry, pkg: Package) -> Result<()> {
+        let staging = dir.path().join("staging");
+        write_package(&staging, "new");
+        let options = InstallOptions {
+            mode: InstallMode::Reinstall,
+            ..Default::default()
+        };
+        replace_installed(
+            pkg,
+            &staging,
+            vec![],
+            &dir.path().join("packages"),
+            &dir.path().join("bin"),
+            registry,
+            &options,
+        )
+    }
 
-    log::info!("Added package to local registry");
+    fn registered_version(dir: &TempDir) -> Version {
+        let registry = crate::util::list_installed_packages(&dir.path().join("registry.json"));
+        registry.unwrap()[0].package.version.clone()
+    }
 
-    Ok(())
+    #[test]
+    fn replaces_installed_version() {
+        let (dir, mut registry) = installed();
+
+        replace(&dir, &mut registry, package("2.0.0", vec![])).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("bin/run")).unwrap(),
+            "new"
+        );
+        assert_eq!(registered_version(&dir), Version::new(2, 0, 0));
+        //nothing is left behind in the package directory
+        let entries: Vec<_> = fs::read_dir(dir.path().join("packages"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["pkg"]);
+    }
+
+    #[test]
+    fn restores_old_version_when_linking_fails() {
+        let (dir, mut registry) = installed();
+
+        //the new version adds another executable to path, but something is already in the way
+        let conflict = dir.path().join("bin/other");
+        fs::write(&conflict, "not ours").unwrap();
+        let entry_point = EntryPoint {
+            name: "other".to_string(),
+            path: "bin/run".to_string(),
+            add_to_path: true,
+        };
+
+        let result = replace(&dir, &mut registry, package("2.0.0", vec![entry_point]));
+        assert!(result.is_err());
+
+        //the old version and its link are back, and nothing else was touched
+        let install_dir = dir.path().join("packages/pkg");
+        assert_eq!(
+            fs::read_to_string(install_dir.join("bin/run")).unwrap(),
+            "old"
+        );
+        assert_eq!(
+            fs::read_link(dir.path().join("bin/run")).unwrap(),
+            install_dir.join("bin/run")
+        );
+        assert_eq!(fs::read_to_string(&conflict).unwrap(), "not ours");
+        assert_eq!(registered_version(&dir), Version::new(1, 0, 0));
+        let bin_entries = fs::read_dir(dir.path().join("bin")).unwrap().count();
+        assert_eq!(bin_entries, 2);
+    }
 }
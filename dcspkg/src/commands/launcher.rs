@@ -0,0 +1,81 @@
+use super::run::expand_pkgdir;
+use crate::{Package, RunConfig};
+use anyhow::{bail, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Launchers are small, so anything bigger than this in the bin directory isn't one of ours
+const MAX_LAUNCHER_SIZE: u64 = 64 * 1024;
+
+/// The first lines of every launcher, which mark it as belonging to the package in `install_dir`
+fn header(install_dir: &Path) -> String {
+    format!(
+        "#!/bin/sh\n# dcspkg launcher for {}, do not edit\n",
+        install_dir.display()
+    )
+}
+
+/// Writes a shell script to `path` that sets up the package's working directory and environment
+/// from its run config, then execs `exe_path` with its default arguments and any others given.
+pub(super) fn write_launcher(
+    pkg: &Package,
+    exe_path: &Path,
+    install_dir: &Path,
+    path: &Path,
+) -> Result<()> {
+    let run = &pkg.run;
+    let mut script = header(install_dir);
+
+    if let Some(cwd) = &run.cwd {
+        script += &format!(
+            "cd {} || exit 1\n",
+            quote(&install_dir.join(cwd).to_string_lossy())
+        );
+    }
+
+    for (key, value) in &run.env {
+        //the key goes into the script unquoted, so it must be a valid shell variable name
+        if !RunConfig::is_valid_env_name(key) {
+            bail!("Invalid environment variable name {key:?} in package run config");
+        }
+        script += &format!(
+            "export {key}={}\n",
+            quote(&expand_pkgdir(value, install_dir))
+        );
+    }
+
+    script += &format!("exec {}", quote(&exe_path.to_string_lossy()));
+    for arg in &run.args {
+        script += &format!(" {}", quote(&expand_pkgdir(arg, install_dir)));
+    }
+    script += " \"$@\"\n";
+
+    //like a symlink, refuse to replace anything already there
+    log::info!("Writing launcher for {exe_path:?} at {path:?}");
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o755)
+        .open(path)
+        .and_then(|mut file| file.write_all(script.as_bytes()))
+        .context("Could not write launcher script")
+}
+
+/// Whether the file at `path` is a launcher written by `write_launcher` for the package in `install_dir`
+pub(super) fn is_launcher_for(path: &Path, install_dir: &Path) -> bool {
+    let header = header(install_dir);
+    let mut contents = String::new();
+
+    fs::symlink_metadata(path).map_or(false, |meta| meta.is_file())
+        && fs::File::open(path)
+            .and_then(|file| file.take(MAX_LAUNCHER_SIZE).read_to_string(&mut contents))
+            .is_ok()
+        && contents.starts_with(&header)
+}
+
+/// Single quotes a string for the shell
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
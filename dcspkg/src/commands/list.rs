@@ -2,16 +2,57 @@ use crate::Package;
 use anyhow::{bail, Context, Result};
 use reqwest::{blocking::get, IntoUrl, StatusCode};
 
+/// How many packages to ask the server for at a time
+const PAGE_SIZE: usize = 100;
+
 /// Returns a vector containing a list of packages that are available
-/// for installation from the dcspkg server.
-pub fn list_all_packages<U: IntoUrl>(url: U) -> Result<Vec<Package>> {
+/// for installation from the dcspkg server, optionally only those with the given tag.
+/// `sort` is passed to the server, and may be "name", "updated" or "size".
+/// The list is fetched a page at a time.
+pub fn list_all_packages<U: IntoUrl>(
+    url: U,
+    tag: Option<&str>,
+    sort: Option<&str>,
+) -> Result<Vec<Package>> {
     //craft URL
-    let url: reqwest::Url = url
+    let mut url: reqwest::Url = url
         .into_url()
         .map_err(anyhow::Error::from)
         .and_then(|url| url.join(crate::LIST_ENDPOINT).map_err(|e| e.into()))
         .context("Could not parse URL")?;
+    if let Some(tag) = tag {
+        url.query_pairs_mut().append_pair("tag", tag);
+    }
+    if let Some(sort) = sort {
+        url.query_pairs_mut().append_pair("sort", sort);
+    }
+
+    let mut list = vec![];
+    loop {
+        let mut page_url = url.clone();
+        page_url
+            .query_pairs_mut()
+            .append_pair("limit", &PAGE_SIZE.to_string())
+            .append_pair("offset", &list.len().to_string());
 
+        let (page, total) = get_page(&page_url)?;
+        let done = page.is_empty();
+        list.extend(page);
+
+        //older servers don't page, and send everything at once without a total
+        match total {
+            Some(total) if !done && list.len() < total => continue,
+            _ => break,
+        }
+    }
+
+    log::debug!("Package list: {list:?}");
+
+    Ok(list)
+}
+
+/// Fetches a page of the package list, along with the total number of packages if the server gave one
+fn get_page(url: &reqwest::Url) -> Result<(Vec<Package>, Option<usize>)> {
     log::info!("Downloading package list from {url}...");
 
     //fetch the list
@@ -23,9 +64,11 @@ pub fn list_all_packages<U: IntoUrl>(url: U) -> Result<Vec<Package>> {
             response.status().as_u16()
         )
     }
-    let list: Vec<Package> = response.json().context("Could not parse JSON response")?;
+    let total = response
+        .headers()
+        .get(crate::TOTAL_COUNT_HEADER)
+        .and_then(|total| total.to_str().ok()?.parse().ok());
+    let page: Vec<Package> = response.json().context("Could not parse JSON response")?;
 
-    log::debug!("Package list: {list:?}");
-
-    Ok(list)
+    Ok((page, total))
 }
@@ -1,5 +1,19 @@
+mod info;
 mod install;
+mod launcher;
 mod list;
 mod run;
+mod search;
+mod uninstall;
+mod unpack;
+mod upgrade;
 
-pub use {install::install_package, list::list_all_packages, run::run_package};
+pub use {
+    info::{get_package_info, InstalledInfo, PackageInfo},
+    install::{install_package, InstallMode, InstallOptions},
+    list::list_all_packages,
+    run::run_package,
+    search::search_packages,
+    uninstall::uninstall_package,
+    upgrade::{list_upgrades, upgrade_package, Upgrade},
+};
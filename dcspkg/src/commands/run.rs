@@ -5,25 +5,63 @@ use std::path::{Path, PathBuf};
 
 /// Launches the specified package. This exits the current process
 /// and launches the package in its place.
+/// The package may be given as `name:entry` to launch one of its other entry points.
+/// `args` are passed after the package's default arguments, and the package's
+/// working directory and environment variables are set up before launching.
 pub fn run_package(
     registry_file: &Path,
     install_dir: PathBuf,
-    package: &String,
+    package: &str,
+    args: &[String],
 ) -> anyhow::Result<()> {
+    let (package, entry) = match package.split_once(':') {
+        Some((package, entry)) => (package, Some(entry)),
+        None => (package, None),
+    };
+
     let package_data = list_installed_packages(registry_file)?
         .into_iter()
-        .find(|pkg| pkg.pkgname == *package)
+        .map(|installed| installed.package)
+        .find(|pkg| pkg.pkgname == package)
         .context(format!(
             "Could not find a package with the name {} in {:?}",
             package, registry_file
         ))?;
+    package_data.check_paths()?;
 
-    let exe_path = install_dir.join(package_data.pkgname).join(
-        package_data
-            .executable_path
+    let relative_exe_path = match entry {
+        Some(entry) => package_data.entry_point(Some(entry)).context(format!(
+            "Package {package} has no entry point named {entry}"
+        ))?,
+        None => package_data
+            .entry_point(None)
             .context("No executable exists for this package")?,
-    );
+    };
+
+    let package_dir = install_dir.join(&package_data.pkgname);
+    let exe_path = package_dir.join(relative_exe_path);
+    let run = &package_data.run;
+
+    let mut command = std::process::Command::new(exe_path);
+    command
+        .args(run.args.iter().map(|arg| expand_pkgdir(arg, &package_dir)))
+        .args(args)
+        .envs(
+            run.env
+                .iter()
+                .map(|(key, value)| (key, expand_pkgdir(value, &package_dir))),
+        );
+    if let Some(cwd) = &run.cwd {
+        command.current_dir(package_dir.join(cwd));
+    }
+
+    log::info!("Running {command:?}");
 
     //will only return if there is an error
-    Err(std::process::Command::new(exe_path).exec()).map_err(Into::into)
+    Err(command.exec().into())
+}
+
+/// Replaces `${PKGDIR}` in a value from a package's run config with its install directory
+pub(super) fn expand_pkgdir(value: &str, package_dir: &Path) -> String {
+    value.replace("${PKGDIR}", &package_dir.to_string_lossy())
 }
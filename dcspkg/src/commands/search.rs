@@ -0,0 +1,31 @@
+use crate::Package;
+use anyhow::{bail, Context, Result};
+use reqwest::{blocking::get, IntoUrl, StatusCode};
+
+/// Searches the packages on the dcspkg server by name and description,
+/// returning those that match every term, best match first.
+pub fn search_packages<U: IntoUrl>(query: &str, url: U) -> Result<Vec<Package>> {
+    //craft URL
+    let mut url: reqwest::Url = url
+        .into_url()
+        .map_err(anyhow::Error::from)
+        .and_then(|url| url.join(crate::SEARCH_ENDPOINT).map_err(|e| e.into()))
+        .context("Could not parse URL")?;
+    url.query_pairs_mut().append_pair("q", query);
+
+    log::info!("Searching packages at {url}...");
+
+    let response = get(url.as_ref()).context("Request failed")?;
+    log::info!("Got reponse from {url}");
+    if response.status() != StatusCode::OK {
+        bail!(
+            "Response was not okay (got code {})",
+            response.status().as_u16()
+        )
+    }
+    let list: Vec<Package> = response.json().context("Could not parse JSON response")?;
+
+    log::debug!("Search results: {list:?}");
+
+    Ok(list)
+}
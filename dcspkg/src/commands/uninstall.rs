@@ -0,0 +1,130 @@
+use super::launcher::is_launcher_for;
+use crate::util::update_installed_packages;
+use crate::InstalledPackage;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Uninstalls the specified package, removing its install directory,
+/// any symlinks to it in the bin directory, and its registry entry.
+/// Packages that are not in the registry are left untouched.
+pub fn uninstall_package<P: AsRef<Path>>(
+    pkg_name: &str,   //the packages pkgname
+    package_dir: P,   //the local package install dir, from config
+    bin_dir: P,       //the local bin install dir, from config
+    registry_file: P, //the local json registry file, from config
+) -> Result<()> {
+    let package_dir = package_dir.as_ref();
+    let bin_dir = bin_dir.as_ref();
+    let registry_file = registry_file.as_ref();
+
+    check_pkg_name(pkg_name).context("Refusing to uninstall package")?;
+
+    let install_dir = package_dir.join(pkg_name);
+
+    //hold the registry for the whole uninstall, so nothing else can change the package under us
+    update_installed_packages(registry_file, |installed| {
+        //only uninstall things that we installed
+        let index = installed
+            .iter()
+            .position(|installed| installed.package.pkgname == pkg_name)
+            .context(format!(
+                "Could not find a package with the name {pkg_name} in {registry_file:?}"
+            ))?;
+
+        let links = installed_links(&installed[index], &install_dir, bin_dir)?;
+        remove_symlinks(bin_dir, &links, &install_dir)
+            .context("Could not remove symlinks from bin directory")?;
+
+        if install_dir.is_dir() {
+            log::info!("Removing package directory {install_dir:?}");
+            fs::remove_dir_all(&install_dir).context("Could not remove package directory")?;
+        } else {
+            log::warn!("Package directory {install_dir:?} does not exist, skipping");
+        }
+
+        installed.remove(index);
+        Ok(())
+    })?;
+
+    log::info!("Removed package from local registry");
+
+    Ok(())
+}
+
+/// Makes sure a package name can't be used to escape the package directory
+pub(super) fn check_pkg_name(pkg_name: &str) -> Result<()> {
+    let mut components = Path::new(pkg_name).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        bail!("Invalid package name {pkg_name:?}");
+    }
+    Ok(())
+}
+
+/// The links that were added to path for a package installed in `install_dir`:
+/// those recorded for it, along with anything else in `bin_dir` that `owns_link` says is its own
+pub(super) fn installed_links(
+    installed: &InstalledPackage,
+    install_dir: &Path,
+    bin_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut links = installed.manifest.links.clone();
+    for link in list_dir(bin_dir).context("Could not read bin directory")? {
+        if !links.contains(&link) && owns_link(installed, install_dir, bin_dir, &link) {
+            links.push(link);
+        }
+    }
+    Ok(links)
+}
+
+/// Whether `link` in `bin_dir` was added to path for a package installed in `install_dir`
+pub(super) fn owns_link(
+    installed: &InstalledPackage,
+    install_dir: &Path,
+    bin_dir: &Path,
+    link: &Path,
+) -> bool {
+    //registries from older versions have no record of the links created,
+    //so fall back to checking where the link leads
+    if installed.manifest.links.is_empty() {
+        is_link_to(bin_dir, link, install_dir)
+    } else {
+        installed.manifest.links.iter().any(|owned| owned == link)
+    }
+}
+
+/// Lists the paths of everything in `dir`, or nothing if it does not exist
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()).map_err(Into::into))
+        .collect()
+}
+
+/// Removes each of `links` that is a symlink pointing to a path within `install_dir`,
+/// or a launcher script for it. Anything else is left alone.
+pub(super) fn remove_symlinks(bin_dir: &Path, links: &[PathBuf], install_dir: &Path) -> Result<()> {
+    for link in links
+        .iter()
+        .filter(|link| is_link_to(bin_dir, link, install_dir))
+    {
+        log::info!("Removing {link:?}");
+        fs::remove_file(link).context(format!("Could not remove {link:?}"))?;
+    }
+
+    Ok(())
+}
+
+/// Whether `link` is a symlink pointing to a path within `install_dir`, or a launcher script for it
+pub(super) fn is_link_to(bin_dir: &Path, link: &Path, install_dir: &Path) -> bool {
+    is_launcher_for(link, install_dir)
+        || fs::read_link(link).map_or(false, |target| {
+            bin_dir.join(target).starts_with(install_dir)
+        })
+}
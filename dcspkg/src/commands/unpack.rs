@@ -0,0 +1,353 @@
+use crate::manifest::{hash_file, ManifestFile};
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::GzDecoder;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
+
+/// Permission bits that are stripped from everything unpacked (setuid and setgid)
+const STRIPPED_MODE_BITS: u32 = 0o6000;
+
+/// Unpacks a compressed package archive into `install_dir`, returning a record of each file written.
+/// Each entry is checked before it is unpacked, and unpacking fails if an entry would escape
+/// `install_dir`, is anything other than a file, directory or link, or takes the archive over
+/// `max_size` bytes unpacked.
+pub(super) fn unpack_archive(
+    compressed: &[u8],
+    install_dir: &Path,
+    max_size: u64,
+) -> Result<Vec<ManifestFile>> {
+    let mut archive = Archive::new(GzDecoder::new(compressed));
+    archive.set_mask(STRIPPED_MODE_BITS);
+
+    fs::create_dir_all(install_dir).context("Could not create package directory")?;
+    let mut files = vec![];
+    let mut total_size: u64 = 0;
+    //the symlinks unpacked so far. Nothing may be unpacked or resolved through these,
+    //as where they lead on disk isn't where they look like they lead
+    let mut symlinks = HashSet::new();
+
+    for entry in archive.entries().context("Could not read archive")? {
+        let mut entry = entry.context("Could not read archive entry")?;
+        let raw_path = entry
+            .path()
+            .context("Could not read path of archive entry")?
+            .into_owned();
+
+        let path = normalise_path(&raw_path)
+            .ok_or_else(|| anyhow!("Archive entry {raw_path:?} escapes the package directory"))?;
+        if through_symlink(&path, &symlinks) {
+            bail!("Archive entry {raw_path:?} is within, or replaces, a symlink in the archive");
+        }
+
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            EntryType::Regular | EntryType::Continuous | EntryType::Directory => (),
+            EntryType::Symlink | EntryType::Link => {
+                if path.as_os_str().is_empty() {
+                    bail!("Archive entry {raw_path:?} is a link in place of the package directory");
+                }
+                let target = entry
+                    .link_name()
+                    .context(format!("Could not read link target of {raw_path:?}"))?
+                    .context(format!(
+                        "Archive entry {raw_path:?} is a link with no target"
+                    ))?;
+
+                //hard link targets are relative to the archive root,
+                //symlink targets are relative to the link
+                //a hard link to a symlink is another symlink, which could point anywhere from its new place
+                let resolved = if entry_type == EntryType::Link {
+                    normalise_path(&target).filter(|target| !through_symlink(target, &symlinks))
+                } else {
+                    resolve_symlink(&path, &target)
+                };
+
+                if resolved.is_none() {
+                    bail!("Archive entry {raw_path:?} links to {target:?}, outside the package directory");
+                }
+            }
+            //global pax headers only carry metadata
+            EntryType::XGlobalHeader => continue,
+            other => bail!("Archive entry {raw_path:?} has unsupported type {other:?}"),
+        }
+
+        total_size = total_size.saturating_add(entry.header().size()?);
+        if total_size > max_size {
+            bail!("Archive entry {raw_path:?} takes the package over the maximum unpacked size of {max_size} bytes");
+        }
+
+        entry
+            .unpack_in(install_dir)
+            .context(format!("Could not unpack {path:?} from archive"))?;
+
+        if entry_type == EntryType::Symlink {
+            symlinks.insert(path.clone());
+        }
+
+        if entry_type.is_file() {
+            files.push(ManifestFile {
+                sha256: hash_file(&install_dir.join(&path))
+                    .context(format!("Could not hash unpacked file {path:?}"))?,
+                size: entry.header().size()?,
+                mode: entry.header().mode()? & !STRIPPED_MODE_BITS,
+                path,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+/// Strips `.` components from a relative path.
+/// Returns `None` if the path is absolute or contains `..`.
+fn normalise_path(path: &Path) -> Option<PathBuf> {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `path`, or any directory it is within, is one of `symlinks`
+fn through_symlink(path: &Path, symlinks: &HashSet<PathBuf>) -> bool {
+    path.ancestors().any(|ancestor| symlinks.contains(ancestor))
+}
+
+/// Works out where a symlink at `link` (relative to the package root) pointing to `target` leads.
+/// Returns `None` if it leads outside the package root, or has `..` after a normal component.
+/// `..` after a component that is, or is later unpacked as, a symlink doesn't lead where it looks like it does,
+/// so only the link's own directories can be gone up through. Those are always real directories,
+/// as nothing is unpacked through a symlink, and a directory can't be replaced with one.
+fn resolve_symlink(link: &Path, target: &Path) -> Option<PathBuf> {
+    let mut resolved = link.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+    let mut descended = false;
+    for component in target.components() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                descended = true;
+            }
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if descended || !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::os::unix::fs::PermissionsExt;
+    use tar::{Builder, Header};
+    use tempfile::TempDir;
+
+    const MAX_SIZE: u64 = 1024;
+
+    /// A header for an archive entry. The path is written as is, so it can be absolute or contain `..`
+    fn header(path: &str, entry_type: EntryType, size: u64, mode: u32) -> Header {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        header.set_mode(mode);
+        header
+    }
+
+    fn file(path: &str, contents: &'static [u8], mode: u32) -> (Header, &'static [u8]) {
+        (
+            header(path, EntryType::Regular, contents.len() as u64, mode),
+            contents,
+        )
+    }
+
+    fn link(path: &str, entry_type: EntryType, target: &str) -> (Header, &'static [u8]) {
+        let mut header = header(path, entry_type, 0, 0o777);
+        header.set_link_name(target).unwrap();
+        (header, b"")
+    }
+
+    fn archive(entries: Vec<(Header, &[u8])>) -> Vec<u8> {
+        let mut builder = Builder::new(GzEncoder::new(vec![], Compression::fast()));
+        for (mut header, data) in entries {
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Unpacks into `pkg` within a new temporary directory, so anything escaping it can be seen
+    fn unpack(entries: Vec<(Header, &[u8])>) -> (TempDir, Result<Vec<ManifestFile>>) {
+        let dir = tempfile::tempdir().unwrap();
+        let result = unpack_archive(&archive(entries), &dir.path().join("pkg"), MAX_SIZE);
+        (dir, result)
+    }
+
+    /// Whether anything other than the package directory was created
+    fn escaped(dir: &TempDir) -> bool {
+        fs::read_dir(dir.path())
+            .unwrap()
+            .any(|entry| entry.unwrap().file_name() != "pkg")
+    }
+
+    #[test]
+    fn unpacks_files_and_links() {
+        let (dir, result) = unpack(vec![
+            (header("bin", EntryType::Directory, 0, 0o755), b""),
+            file("bin/run", b"#!/bin/sh\n", 0o755),
+            link("run", EntryType::Symlink, "bin/run"),
+            link("run2", EntryType::Link, "bin/run"),
+        ]);
+
+        let files = result.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, Path::new("bin/run"));
+        assert_eq!(files[0].size, 10);
+        assert_eq!(
+            files[0].sha256,
+            hash_file(&dir.path().join("pkg/bin/run")).unwrap()
+        );
+        assert_eq!(
+            fs::read_link(dir.path().join("pkg/run")).unwrap(),
+            Path::new("bin/run")
+        );
+        assert!(dir.path().join("pkg/run2").is_file());
+    }
+
+    #[test]
+    fn strips_setuid_and_setgid() {
+        let (dir, result) = unpack(vec![file("run", b"", 0o6755)]);
+
+        assert_eq!(result.unwrap()[0].mode, 0o755);
+        let mode = fs::metadata(dir.path().join("pkg/run"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o755);
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        let (dir, result) = unpack(vec![file("/tmp/dcspkg-absolute", b"", 0o644)]);
+        assert!(result.is_err());
+        assert!(!Path::new("/tmp/dcspkg-absolute").exists());
+        assert!(!escaped(&dir));
+    }
+
+    #[test]
+    fn rejects_parent_dirs() {
+        for path in ["../out", "a/../../out"] {
+            let (dir, result) = unpack(vec![file(path, b"", 0o644)]);
+            assert!(result.is_err(), "{path} was unpacked");
+            assert!(!escaped(&dir));
+        }
+    }
+
+    #[test]
+    fn rejects_escaping_symlinks() {
+        for target in ["../out", "/etc", "a/../../out"] {
+            let (dir, result) = unpack(vec![link("l", EntryType::Symlink, target)]);
+            assert!(result.is_err(), "link to {target} was unpacked");
+            assert!(!escaped(&dir));
+        }
+    }
+
+    #[test]
+    fn rejects_symlinks_through_symlinks() {
+        //`d/..` looks like the package root, but as `d` is the package root itself, it is really the directory above
+        let (dir, result) = unpack(vec![
+            link("d", EntryType::Symlink, "."),
+            link("e", EntryType::Symlink, "d/.."),
+        ]);
+        assert!(result.is_err());
+        assert!(fs::symlink_metadata(dir.path().join("pkg/e")).is_err());
+
+        //the same the other way round, where `d` isn't a symlink yet when `e` is unpacked
+        let (dir, result) = unpack(vec![
+            link("e", EntryType::Symlink, "d/.."),
+            link("d", EntryType::Symlink, "."),
+        ]);
+        assert!(result.is_err());
+        assert!(fs::symlink_metadata(dir.path().join("pkg/e")).is_err());
+
+        //going up through the link's own directories is fine
+        let (_, result) = unpack(vec![
+            (header("sub", EntryType::Directory, 0, 0o755), b""),
+            link("sub/l", EntryType::Symlink, "../sub/x"),
+        ]);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn rejects_entries_through_symlinks() {
+        let (dir, result) = unpack(vec![
+            (header("sub", EntryType::Directory, 0, 0o755), b""),
+            link("d", EntryType::Symlink, "sub"),
+            file("d/file", b"", 0o644),
+        ]);
+        assert!(result.is_err());
+        assert!(!dir.path().join("pkg/sub/file").exists());
+
+        //replacing a symlink is refused too
+        let (_, result) = unpack(vec![
+            link("d", EntryType::Symlink, "."),
+            file("d", b"", 0o644),
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_escaping_hard_links() {
+        for target in ["/etc/passwd", "../out", "d/file"] {
+            let (dir, result) = unpack(vec![
+                link("d", EntryType::Symlink, "."),
+                link("h", EntryType::Link, target),
+            ]);
+            assert!(result.is_err(), "hard link to {target} was unpacked");
+            assert!(!dir.path().join("pkg/h").exists());
+        }
+
+        //`sub/s` leads to the package root, but a hard link to it is a `..` symlink at the root
+        let (dir, result) = unpack(vec![
+            (header("sub", EntryType::Directory, 0, 0o755), b""),
+            link("sub/s", EntryType::Symlink, ".."),
+            link("h", EntryType::Link, "sub/s"),
+        ]);
+        assert!(result.is_err());
+        assert!(fs::symlink_metadata(dir.path().join("pkg/h")).is_err());
+    }
+
+    #[test]
+    fn rejects_device_nodes() {
+        for entry_type in [EntryType::Char, EntryType::Block, EntryType::Fifo] {
+            let (dir, result) = unpack(vec![(header("dev", entry_type, 0, 0o644), b"")]);
+            assert!(result.is_err(), "{entry_type:?} was unpacked");
+            assert!(fs::symlink_metadata(dir.path().join("pkg/dev")).is_err());
+        }
+    }
+
+    #[test]
+    fn enforces_size_cap() {
+        const HALF: &[u8] = &[0; MAX_SIZE as usize / 2];
+
+        let (_, result) = unpack(vec![file("a", HALF, 0o644), file("b", HALF, 0o644)]);
+        assert!(result.is_ok());
+
+        let (dir, result) = unpack(vec![
+            file("a", HALF, 0o644),
+            file("b", HALF, 0o644),
+            file("c", b"x", 0o644),
+        ]);
+        assert!(result.is_err());
+        assert!(!dir.path().join("pkg/c").exists());
+    }
+}
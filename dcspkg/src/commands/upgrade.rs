@@ -0,0 +1,103 @@
+use super::install::{install_resolved, same_contents, InstallMode, InstallOptions};
+use crate::config::{Limits, Trust};
+use crate::util::{list_installed_packages, LockedRegistry};
+use crate::{list_all_packages, Package};
+use anyhow::{Context, Result};
+use reqwest::IntoUrl;
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// A package that has a newer version available on the server than the one installed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upgrade {
+    /// The package as it is currently installed
+    pub installed: Package,
+    /// The latest version of the package on the server
+    pub available: Package,
+}
+
+/// Compares the installed packages against the latest versions on the server,
+/// and returns those that can be upgraded. If `pkg_name` is given, only that package is checked.
+pub fn list_upgrades<U: IntoUrl>(
+    pkg_name: Option<&str>, //only check this package
+    server_url: U,          //the url of the server, from config
+    registry_file: &Path,   //the local json registry file, from config
+) -> Result<Vec<Upgrade>> {
+    let installed: Vec<Package> = list_installed_packages(registry_file)?
+        .into_iter()
+        .map(|installed| installed.package)
+        .filter(|pkg| pkg_name.map_or(true, |name| pkg.pkgname == name))
+        .collect();
+
+    if let Some(name) = pkg_name {
+        installed
+            .first()
+            .context(format!("Package {name} is not installed"))?;
+    }
+
+    let available = list_all_packages(server_url, None, None)?;
+
+    Ok(installed
+        .into_iter()
+        .filter_map(|installed| {
+            available
+                .iter()
+                .find(|pkg| pkg.pkgname == installed.pkgname && is_newer(pkg, &installed))
+                .cloned()
+                .map(|available| Upgrade {
+                    installed,
+                    available,
+                })
+        })
+        .collect())
+}
+
+/// Packages can be republished without changing version, so compare checksums if the versions match
+fn is_newer(available: &Package, installed: &Package) -> bool {
+    match available.version.cmp(&installed.version) {
+        Ordering::Greater => true,
+        Ordering::Equal => !same_contents(available, installed),
+        Ordering::Less => false,
+    }
+}
+
+/// Replaces an installed package with the version available on the server.
+/// This is a reinstall of the new version under the same name on path,
+/// so the new version is unpacked into a staging directory first,
+/// and only swapped in once it is ready. The registry is locked for the whole upgrade.
+pub fn upgrade_package<P: AsRef<Path>>(
+    upgrade: &Upgrade,                 //the upgrade to perform, from list_upgrades
+    server_url: impl reqwest::IntoUrl, //the url of the server, from config
+    package_dir: P,                    //the local package install dir, from config
+    bin_dir: P,                        //the local bin install dir, from config
+    registry_file: P,                  //the local json registry file, from config
+    trust: &Trust,                     //the trusted signing keys, from config
+    limits: &Limits,                   //limits on what the package may do, from config
+) -> Result<()> {
+    let server_url = server_url
+        .into_url()
+        .context("Could not parse server URL")?;
+
+    let pkg = &upgrade.available;
+    let mut registry = LockedRegistry::open(registry_file.as_ref())?;
+    let installed = registry
+        .packages
+        .iter()
+        .find(|installed| installed.package.pkgname == pkg.pkgname)
+        .context(format!("Package {} is not installed", pkg.pkgname))?;
+
+    let options = InstallOptions {
+        mode: InstallMode::Reinstall,
+        alias: installed.alias.clone(),
+        limits: limits.clone(),
+    };
+    install_resolved(
+        pkg.clone(),
+        &server_url,
+        package_dir.as_ref(),
+        bin_dir.as_ref(),
+        &mut registry,
+        trust,
+        &options,
+    )
+}
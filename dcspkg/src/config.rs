@@ -12,6 +12,11 @@ lazy_static::lazy_static! {
 pub struct DcspkgConfig {
     pub server: Server,
     pub registry: Registry,
+    //config files from older versions have no trust section
+    #[serde(default)]
+    pub trust: Trust,
+    #[serde(default)]
+    pub limits: Limits,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -44,6 +49,32 @@ impl Default for Registry {
     }
 }
 
+/// Which package signatures are trusted
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Trust {
+    /// Hex encoded ed25519 public keys that packages may be signed with
+    pub keys: Vec<String>,
+    /// Install packages that are unsigned, or not signed by a trusted key
+    pub allow_untrusted: bool,
+}
+
+/// Limits on what installing a package may do
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Limits {
+    /// The most a package may unpack to in bytes, so a malicious archive can't fill the disk
+    pub max_unpacked_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_unpacked_size: 4 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
 impl DcspkgConfig {
     pub fn get() -> anyhow::Result<Self> {
         let config_file_path = DCSPKG_DIR.join("config.toml");
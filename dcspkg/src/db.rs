@@ -0,0 +1,595 @@
+use crate::{Package, RunConfig};
+use anyhow::{bail, Context, Result};
+use semver::Version;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool, SqliteRow};
+use sqlx::{Connection, Executor, FromRow, Row, Sqlite, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// The migrations that make up the database schema, in order.
+/// Applying the first `n` of them gives schema version `n`.
+/// Never change or remove one once it's been released, add a new one instead.
+const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "0001_initial",
+        include_str!("../migrations/0001_initial.sql"),
+    ),
+    (
+        "0002_versions",
+        include_str!("../migrations/0002_versions.sql"),
+    ),
+    ("0003_sha256", include_str!("../migrations/0003_sha256.sql")),
+    (
+        "0004_signatures",
+        include_str!("../migrations/0004_signatures.sql"),
+    ),
+    (
+        "0005_bin_name",
+        include_str!("../migrations/0005_bin_name.sql"),
+    ),
+    (
+        "0006_entry_points",
+        include_str!("../migrations/0006_entry_points.sql"),
+    ),
+    (
+        "0007_run_config",
+        include_str!("../migrations/0007_run_config.sql"),
+    ),
+    ("0008_search", include_str!("../migrations/0008_search.sql")),
+    ("0009_tags", include_str!("../migrations/0009_tags.sql")),
+    (
+        "0010_size_updated",
+        include_str!("../migrations/0010_size_updated.sql"),
+    ),
+    ("0011_yanked", include_str!("../migrations/0011_yanked.sql")),
+    ("0012_latest", include_str!("../migrations/0012_latest.sql")),
+];
+
+/// The schema version this build of dcspkg expects
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Gets the schema version of the database, which is kept in sqlite's `user_version`
+pub async fn schema_version(conn: &mut SqliteConnection) -> Result<i64> {
+    sqlx::query_scalar("PRAGMA user_version")
+        .fetch_one(conn)
+        .await
+        .context("Could not get database schema version")
+}
+
+/// Brings the database schema up to date, applying each migration it doesn't have yet.
+/// Each migration is applied in its own transaction, along with bumping the schema version.
+/// Fails without touching the database if it has a newer schema than this build knows about.
+pub async fn migrate(conn: &mut SqliteConnection) -> Result<()> {
+    let current = schema_version(conn).await?;
+
+    if current > SCHEMA_VERSION {
+        bail!(
+            "Database schema version {current} is newer than the latest this build of dcspkg supports ({SCHEMA_VERSION}), please upgrade"
+        );
+    }
+
+    for (version, (name, sql)) in (1..).zip(MIGRATIONS).skip(current as usize) {
+        log::info!("Applying database migration {name}");
+
+        let mut transaction = conn.begin().await?;
+        transaction
+            .execute(*sql)
+            .await
+            .with_context(|| format!("Could not apply database migration {name}"))?;
+        //pragmas can't take bound parameters
+        transaction
+            .execute(format!("PRAGMA user_version = {version}").as_str())
+            .await
+            .context("Could not update database schema version")?;
+        transaction.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// The most parameters to bind to a single query, well under sqlite's limit
+const MAX_PARAMETERS: usize = 500;
+
+/// The package database. Every query against it goes through here,
+/// so the server and `dcspkg-create` agree on how packages are stored.
+#[derive(Clone, Debug)]
+pub struct PackageStore {
+    pool: SqlitePool,
+}
+
+impl PackageStore {
+    /// Opens the database at `path`, creating it if it doesn't exist, and brings its schema up to date
+    pub async fn open(path: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(path)
+            .context("Invalid database path")?
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .context("Could not connect to database")?;
+        Self::from_pool(pool).await
+    }
+
+    /// Uses an existing connection pool, bringing the database's schema up to date first
+    pub async fn from_pool(pool: SqlitePool) -> Result<Self> {
+        migrate(&mut *pool.acquire().await?).await?;
+        let store = PackageStore { pool };
+        store
+            .mark_unmarked_latest()
+            .await
+            .context("Could not work out the latest version of each package")?;
+        Ok(store)
+    }
+
+    /// Works out the latest versions of packages that haven't had it worked out yet,
+    /// ie those added before the `latest` column existed
+    async fn mark_unmarked_latest(&self) -> Result<(), sqlx::Error> {
+        let names: Vec<String> =
+            sqlx::query_scalar("SELECT DISTINCT pkgname FROM packages WHERE latest IS NULL")
+                .fetch_all(&self.pool)
+                .await?;
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        log::info!("Working out the latest version of {} packages", names.len());
+        let mut transaction = self.begin().await?;
+        for name in &names {
+            transaction.mark_latest(name).await?;
+        }
+        transaction.commit().await
+    }
+
+    /// Gets the latest version of the package with the given name that hasn't been yanked
+    pub async fn latest(&self, name: &str) -> Result<Option<Package>, sqlx::Error> {
+        self.versions(name)
+            .await
+            .map(|versions| versions.into_iter().rev().find(|pkg| !pkg.yanked))
+    }
+
+    /// Gets a specific version of the package with the given name
+    pub async fn get(&self, name: &str, version: &Version) -> Result<Option<Package>, sqlx::Error> {
+        let mut package: Vec<Package> =
+            sqlx::query_as("SELECT * FROM packages WHERE pkgname=? AND version=?")
+                .bind(name)
+                .bind(version.to_string())
+                .fetch_optional(&self.pool)
+                .await?
+                .into_iter()
+                .collect();
+
+        self.add_tags(&mut package).await?;
+        Ok(package.pop())
+    }
+
+    /// Gets every version of the package with the given name, oldest first, including yanked ones
+    pub async fn versions(&self, name: &str) -> Result<Vec<Package>, sqlx::Error> {
+        let mut versions: Vec<Package> = sqlx::query_as("SELECT * FROM packages WHERE pkgname=?")
+            .bind(name)
+            .fetch_all(&self.pool)
+            .await?;
+
+        //semver ordering can't be done in sql
+        versions.sort_by(|a, b| a.version.cmp(&b.version));
+        self.add_tags(&mut versions).await?;
+        Ok(versions)
+    }
+
+    /// Gets a page of the latest version of every package, or just those with the given tag,
+    /// skipping the first `offset` and returning at most `limit`.
+    /// Yanked versions are left out, along with packages that only have yanked versions
+    pub async fn list(
+        &self,
+        tag: Option<&str>,
+        order: ListOrder,
+        limit: Option<u64>,
+        offset: u64,
+    ) -> Result<Vec<Package>, sqlx::Error> {
+        //packages with no size or update time go last, as nulls sort first
+        let order = match order {
+            ListOrder::Name => "packages.pkgname",
+            ListOrder::Updated => "packages.updated DESC, packages.pkgname",
+            ListOrder::Size => "packages.size DESC, packages.pkgname",
+        };
+        let sql = format!(
+            "SELECT packages.* FROM packages {} WHERE packages.latest ORDER BY {order} LIMIT ? OFFSET ?",
+            tag_join(tag)
+        );
+
+        let mut query = sqlx::query_as(&sql);
+        if let Some(tag) = tag {
+            query = query.bind(tag.to_lowercase());
+        }
+        //a negative limit is no limit
+        let mut packages: Vec<Package> = query
+            .bind(limit.map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX)))
+            .bind(i64::try_from(offset).unwrap_or(i64::MAX))
+            .fetch_all(&self.pool)
+            .await?;
+
+        self.add_tags(&mut packages).await?;
+        Ok(packages)
+    }
+
+    /// Counts the packages `list` would give without a limit, ie those with a version that isn't yanked
+    pub async fn count(&self, tag: Option<&str>) -> Result<u64, sqlx::Error> {
+        let sql = format!(
+            "SELECT COUNT(*) FROM packages {} WHERE packages.latest",
+            tag_join(tag)
+        );
+
+        let mut query = sqlx::query_scalar(&sql);
+        if let Some(tag) = tag {
+            query = query.bind(tag.to_lowercase());
+        }
+        let count: i64 = query.fetch_one(&self.pool).await?;
+        Ok(count as u64)
+    }
+
+    /// Searches package names and descriptions, returning the latest version of each package
+    /// that matches every term, best match first
+    pub async fn search(&self, query: &str) -> Result<Vec<Package>, sqlx::Error> {
+        //quote each term so the user can't write fts syntax, and match prefixes so partial words work
+        let terms: Vec<String> = query
+            .split_whitespace()
+            .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+            .collect();
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        //matches in the name count for more than matches in the description.
+        //each matching version gives the package's latest version
+        let matches: Vec<Package> = sqlx::query_as(
+            "SELECT packages.* FROM packages_fts \
+             JOIN packages ON packages.pkgname = packages_fts.pkgname AND packages.latest \
+             WHERE packages_fts MATCH ? \
+             ORDER BY bm25(packages_fts, 10.0, 0.0, 5.0, 1.0)",
+        )
+        .bind(terms.join(" "))
+        .fetch_all(&self.pool)
+        .await?;
+
+        //each version of a package has its own row, so only keep the best match for each
+        let mut seen = HashSet::new();
+        let mut packages: Vec<Package> = matches
+            .into_iter()
+            .filter(|pkg| seen.insert(pkg.pkgname.clone()))
+            .collect();
+
+        self.add_tags(&mut packages).await?;
+        Ok(packages)
+    }
+
+    /// Gets the tags of the package with the given name, in alphabetical order
+    pub async fn tags(&self, name: &str) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT tag FROM package_tags WHERE pkgname=? ORDER BY tag")
+            .bind(name)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Adds a new version of a package. Fails if that version already exists.
+    /// Tags are shared by every version, so the package's tags are replaced with the new version's
+    pub async fn insert(&self, package: &Package) -> Result<(), sqlx::Error> {
+        let mut transaction = self.begin().await?;
+        transaction.insert(package).await?;
+        transaction.commit().await
+    }
+
+    /// Replaces the metadata of an existing version of a package, and the package's tags.
+    /// Returns false if that version doesn't exist
+    pub async fn update(&self, package: &Package) -> Result<bool, sqlx::Error> {
+        let mut transaction = self.begin().await?;
+        let updated = transaction.update(package).await?;
+        transaction.commit().await?;
+        Ok(updated)
+    }
+
+    /// Removes a version of a package, and the package's tags if it was the last version.
+    /// Returns false if that version doesn't exist
+    pub async fn delete(&self, name: &str, version: &Version) -> Result<bool, sqlx::Error> {
+        let mut transaction = self.begin().await?;
+        let deleted = transaction.delete(name, version).await?;
+        transaction.commit().await?;
+        Ok(deleted)
+    }
+
+    /// Yanks or unyanks a version of a package. Returns false if that version doesn't exist
+    pub async fn set_yanked(
+        &self,
+        name: &str,
+        version: &Version,
+        yanked: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let mut transaction = self.begin().await?;
+        let found = transaction.set_yanked(name, version, yanked).await?;
+        transaction.commit().await?;
+        Ok(found)
+    }
+
+    /// Starts a transaction, for changes that need to happen along with something outside the database,
+    /// ie moving the package archive into place. Nothing is changed unless it's committed
+    pub async fn begin(&self) -> Result<PackageTransaction, sqlx::Error> {
+        Ok(PackageTransaction {
+            transaction: self.pool.begin().await?,
+        })
+    }
+
+    /// Fills in the tags of each package, which are kept in their own table.
+    /// Only the tags of the packages given are looked up
+    async fn add_tags(&self, packages: &mut [Package]) -> Result<(), sqlx::Error> {
+        let mut names: Vec<String> = packages.iter().map(|pkg| pkg.pkgname.clone()).collect();
+        names.sort();
+        names.dedup();
+
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        //sqlite limits how many parameters a query can have
+        for names in names.chunks(MAX_PARAMETERS) {
+            let sql = format!(
+                "SELECT pkgname, tag FROM package_tags WHERE pkgname IN ({}) ORDER BY tag",
+                vec!["?"; names.len()].join(",")
+            );
+            let rows: Vec<(String, String)> = names
+                .iter()
+                .fold(sqlx::query_as(&sql), |query, name| query.bind(name))
+                .fetch_all(&self.pool)
+                .await?;
+
+            for (name, tag) in rows {
+                tags.entry(name).or_default().push(tag);
+            }
+        }
+
+        for pkg in packages {
+            pkg.tags = tags.get(&pkg.pkgname).cloned().unwrap_or_default();
+        }
+        Ok(())
+    }
+}
+
+/// How to order the package list
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListOrder {
+    /// Alphabetically by pkgname
+    #[default]
+    Name,
+    /// Most recently updated first
+    Updated,
+    /// Largest first
+    Size,
+}
+
+/// Joins on the given tag when listing packages, which is bound as the first parameter
+fn tag_join(tag: Option<&str>) -> &'static str {
+    match tag {
+        Some(_) => {
+            "JOIN package_tags ON package_tags.pkgname = packages.pkgname AND package_tags.tag = ?"
+        }
+        None => "",
+    }
+}
+
+/// Changes to the package database that are applied all at once by `commit`,
+/// or not at all if it's dropped first
+pub struct PackageTransaction {
+    transaction: Transaction<'static, Sqlite>,
+}
+
+impl PackageTransaction {
+    /// Adds a new version of a package, see `PackageStore::insert`
+    pub async fn insert(&mut self, package: &Package) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO packages (pkgname, version, fullname, description, image_url, executable_path, crc, sha256, signature, has_installer, add_to_path, bin_name, entry_points, run_config, size, updated, yanked) \
+             VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)",
+        )
+        .bind(&package.pkgname)
+        .bind(package.version.to_string())
+        .bind(&package.fullname)
+        .bind(&package.description)
+        .bind(&package.image_url)
+        .bind(&package.executable_path)
+        .bind(package.crc)
+        .bind(&package.sha256)
+        .bind(&package.signature)
+        .bind(package.has_installer)
+        .bind(package.add_to_path)
+        .bind(&package.bin_name)
+        .bind(json_or_null(&package.entry_points, package.entry_points.is_empty())?)
+        .bind(json_or_null(&package.run, package.run == RunConfig::default())?)
+        .bind(package.size.map(|size| size as i64))
+        .bind(package.updated)
+        .bind(package.yanked)
+        .execute(&mut self.transaction)
+        .await?;
+
+        self.mark_latest(&package.pkgname).await?;
+        self.replace_tags(package).await
+    }
+
+    /// Replaces the metadata of an existing version of a package, see `PackageStore::update`.
+    /// Whether it's yanked is left alone
+    pub async fn update(&mut self, package: &Package) -> Result<bool, sqlx::Error> {
+        let updated = sqlx::query(
+            "UPDATE packages SET fullname=?, description=?, image_url=?, executable_path=?, crc=?, sha256=?, signature=?, has_installer=?, add_to_path=?, bin_name=?, entry_points=?, run_config=?, size=?, updated=? \
+             WHERE pkgname=? AND version=?",
+        )
+        .bind(&package.fullname)
+        .bind(&package.description)
+        .bind(&package.image_url)
+        .bind(&package.executable_path)
+        .bind(package.crc)
+        .bind(&package.sha256)
+        .bind(&package.signature)
+        .bind(package.has_installer)
+        .bind(package.add_to_path)
+        .bind(&package.bin_name)
+        .bind(json_or_null(&package.entry_points, package.entry_points.is_empty())?)
+        .bind(json_or_null(&package.run, package.run == RunConfig::default())?)
+        .bind(package.size.map(|size| size as i64))
+        .bind(package.updated)
+        .bind(&package.pkgname)
+        .bind(package.version.to_string())
+        .execute(&mut self.transaction)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if updated {
+            self.replace_tags(package).await?;
+        }
+        Ok(updated)
+    }
+
+    /// Removes a version of a package, see `PackageStore::delete`
+    pub async fn delete(&mut self, name: &str, version: &Version) -> Result<bool, sqlx::Error> {
+        let deleted = sqlx::query("DELETE FROM packages WHERE pkgname=? AND version=?")
+            .bind(name)
+            .bind(version.to_string())
+            .execute(&mut self.transaction)
+            .await?
+            .rows_affected()
+            > 0;
+
+        sqlx::query(
+            "DELETE FROM package_tags WHERE pkgname=? \
+             AND NOT EXISTS (SELECT 1 FROM packages WHERE pkgname=?)",
+        )
+        .bind(name)
+        .bind(name)
+        .execute(&mut self.transaction)
+        .await?;
+
+        self.mark_latest(name).await?;
+        Ok(deleted)
+    }
+
+    /// Yanks or unyanks a version of a package, see `PackageStore::set_yanked`
+    pub async fn set_yanked(
+        &mut self,
+        name: &str,
+        version: &Version,
+        yanked: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let found = sqlx::query("UPDATE packages SET yanked=? WHERE pkgname=? AND version=?")
+            .bind(yanked)
+            .bind(name)
+            .bind(version.to_string())
+            .execute(&mut self.transaction)
+            .await?
+            .rows_affected()
+            > 0;
+
+        self.mark_latest(name).await?;
+        Ok(found)
+    }
+
+    /// Applies all the changes made in the transaction
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.transaction.commit().await
+    }
+
+    /// Marks which version of the package is the latest that isn't yanked,
+    /// which has to be done whenever a version is added, removed, yanked or unyanked
+    async fn mark_latest(&mut self, name: &str) -> Result<(), sqlx::Error> {
+        let versions: Vec<(String, bool)> =
+            sqlx::query_as("SELECT version, yanked FROM packages WHERE pkgname=?")
+                .bind(name)
+                .fetch_all(&mut self.transaction)
+                .await?;
+
+        //semver ordering can't be done in sql
+        let mut latest: Option<(Version, String)> = None;
+        for (version, yanked) in versions {
+            let parsed: Version = version.parse().map_err(|e| column_error("version", e))?;
+            if !yanked && latest.as_ref().map_or(true, |(max, _)| parsed > *max) {
+                latest = Some((parsed, version));
+            }
+        }
+
+        //versions are never empty, so nothing matches if there is no latest
+        sqlx::query("UPDATE packages SET latest = (version = ?) WHERE pkgname=?")
+            .bind(latest.map(|(_, version)| version).unwrap_or_default())
+            .bind(name)
+            .execute(&mut self.transaction)
+            .await?;
+        Ok(())
+    }
+
+    /// Sets the package's tags to its `tags`, replacing any it already had
+    async fn replace_tags(&mut self, package: &Package) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM package_tags WHERE pkgname=?")
+            .bind(&package.pkgname)
+            .execute(&mut self.transaction)
+            .await?;
+        for tag in &package.tags {
+            sqlx::query("INSERT INTO package_tags (pkgname, tag) VALUES (?,?)")
+                .bind(&package.pkgname)
+                .bind(tag)
+                .execute(&mut self.transaction)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a value for a JSON column, or null if it has nothing worth storing
+fn json_or_null<T: Serialize>(value: &T, empty: bool) -> Result<Option<String>, sqlx::Error> {
+    if empty {
+        return Ok(None);
+    }
+    //sqlx has no error for values that can't be encoded
+    serde_json::to_string(value)
+        .map(Some)
+        .map_err(|e| sqlx::Error::Protocol(format!("Could not encode JSON column: {e}")))
+}
+
+/// Decodes a package from a row of the `packages` table, by column name.
+/// Tags are kept in their own table, so are left empty.
+impl FromRow<'_, SqliteRow> for Package {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Package {
+            pkgname: row.try_get("pkgname")?,
+            version: row
+                .try_get::<String, _>("version")?
+                .parse()
+                .map_err(|e| column_error("version", e))?,
+            fullname: row.try_get("fullname")?,
+            description: row.try_get("description")?,
+            image_url: row.try_get("image_url")?,
+            executable_path: row.try_get("executable_path")?,
+            crc: row.try_get("crc")?,
+            sha256: row.try_get("sha256")?,
+            signature: row.try_get("signature")?,
+            has_installer: row.try_get("has_installer")?,
+            add_to_path: row.try_get("add_to_path")?,
+            bin_name: row.try_get("bin_name")?,
+            entry_points: json_column(row, "entry_points")?.unwrap_or_default(),
+            run: json_column(row, "run_config")?.unwrap_or_default(),
+            tags: vec![],
+            size: row
+                .try_get::<Option<i64>, _>("size")?
+                .map(|size| size as u64),
+            updated: row.try_get("updated")?,
+            yanked: row.try_get("yanked")?,
+        })
+    }
+}
+
+/// Decodes a nullable column holding JSON
+fn json_column<T: DeserializeOwned>(
+    row: &SqliteRow,
+    column: &str,
+) -> Result<Option<T>, sqlx::Error> {
+    row.try_get::<Option<String>, _>(column)?
+        .map(|json| serde_json::from_str(&json).map_err(|e| column_error(column, e)))
+        .transpose()
+}
+
+fn column_error(column: &str, e: impl std::error::Error + Send + Sync + 'static) -> sqlx::Error {
+    sqlx::Error::ColumnDecode {
+        index: column.to_owned(),
+        source: Box::new(e),
+    }
+}
@@ -1,17 +1,34 @@
+use anyhow::{bail, Context};
+use manifest::Manifest;
+use semver::Version;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
 
 mod commands;
 pub mod config;
+/// Access to the package database, shared by the server and `dcspkg-create`
+#[cfg(feature = "db")]
+pub mod db;
+pub mod manifest;
 pub mod util;
 
-pub use crate::commands::{install_package, list_all_packages, run_package};
+pub use crate::commands::{
+    get_package_info, install_package, list_all_packages, list_upgrades, run_package,
+    search_packages, uninstall_package, upgrade_package, InstallMode, InstallOptions,
+    InstalledInfo, PackageInfo, Upgrade,
+};
 
 /// Represents a package, and contains all the metadata assoicated with it.
-#[derive(Deserialize, Default, Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
 pub struct Package {
     /// The package's name, ie "gcc"
-    /// This is the primary key
+    /// This, along with the version, is the primary key
     pub pkgname: String,
+    /// The package's version.
+    /// Packages from before versioning was introduced are version 0.0.0
+    #[serde(default = "unversioned")]
+    pub version: Version,
     /// The game/app's full name/title, ie "The GNU Compiler Collection, Version 4.3"
     pub fullname: String,
     /// A short description of the package
@@ -20,14 +37,199 @@ pub struct Package {
     pub image_url: Option<String>,
     /// The relative path of the executable within the tarball
     pub executable_path: Option<String>,
-    /// The package's CRC checksum
+    /// The package's CRC checksum, of the uncompressed tarball.
+    /// Kept for older clients, newer ones use `sha256` where available
     pub crc: u32,
+    /// The hex encoded SHA-256 digest of the package archive, as served.
+    /// Packages created before this was introduced have none
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// The hex encoded ed25519 signature of the package, if it has been signed.
+    /// This is made over `signed_payload`, so covers the archive's digest and the metadata used to install it
+    #[serde(default)]
+    pub signature: Option<String>,
     /// Does the package have an install script that needs running?
     pub has_installer: bool,
     /// Does the package want to be added to path on the machine it was installed on?
     pub add_to_path: bool,
+    /// The name to add the executable to path as, if not the executable's file name
+    #[serde(default)]
+    pub bin_name: Option<String>,
+    /// Other named executables in the package, besides the default `executable_path`
+    #[serde(default)]
+    pub entry_points: Vec<EntryPoint>,
+    /// How the package's executables should be launched
+    #[serde(default)]
+    pub run: RunConfig,
+    /// Lowercase tags/categories for the package, ie "games" or "compilers"
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The size of the package archive in bytes, if known
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// When this version was added, in seconds since the unix epoch, if known
+    #[serde(default)]
+    pub updated: Option<i64>,
+    /// Has this version been yanked? Yanked versions can still be installed by their version,
+    /// but aren't listed or picked as the latest
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// Settings for launching a package's executables.
+/// `${PKGDIR}` in `env` and `args` is replaced with the package's install directory.
+#[derive(Deserialize, Serialize, Default, Clone, Debug, PartialEq, Eq)]
+pub struct RunConfig {
+    /// The directory to run from, relative to the package.
+    /// The caller's working directory is kept if there is none
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Extra environment variables to set, ie `LD_LIBRARY_PATH = "${PKGDIR}/lib"`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Arguments to pass before any given on the command line
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Should executables be added to path with a launcher script that sets all this up,
+    /// rather than a symlink?
+    #[serde(default)]
+    pub launcher: bool,
+}
+
+impl RunConfig {
+    /// Whether `name` can be used as an environment variable name in `env`.
+    /// Launchers set them from a shell script, so they must be valid shell variable names
+    pub fn is_valid_env_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+}
+
+/// A named executable within a package, that can be run with `dcspkg run <pkgname>:<name>`
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct EntryPoint {
+    /// The entry point's name, ie "server"
+    pub name: String,
+    /// The relative path of the executable within the tarball
+    pub path: String,
+    /// Should the executable be added to path, under its name?
+    #[serde(default)]
+    pub add_to_path: bool,
+}
+
+impl Package {
+    /// The file name of the package's archive on the server
+    pub fn archive_name(&self) -> String {
+        //unversioned packages predate versions being included in the file name
+        if self.version == unversioned() {
+            format!("{}.dcspkg", self.pkgname)
+        } else {
+            format!("{}-{}.dcspkg", self.pkgname, self.version)
+        }
+    }
+
+    /// The relative path of the named entry point within the package,
+    /// or the default executable if no name is given
+    pub fn entry_point(&self, name: Option<&str>) -> Option<&str> {
+        match name {
+            None => self.executable_path.as_deref(),
+            Some(name) => self
+                .entry_points
+                .iter()
+                .find(|entry| entry.name == name)
+                .map(|entry| entry.path.as_str()),
+        }
+    }
+
+    /// The bytes a package's signature is made over: its name and version, the SHA-256 of its archive,
+    /// and everything that decides what is run when installing and running it, as JSON.
+    /// Fails if the package has no SHA-256, as then nothing would tie the signature to the archive
+    pub fn signed_payload(&self) -> anyhow::Result<Vec<u8>> {
+        let payload = SignedPayload {
+            pkgname: &self.pkgname,
+            version: &self.version,
+            sha256: self
+                .sha256
+                .as_deref()
+                .context("Package has no SHA-256 digest to sign")?,
+            executable_path: &self.executable_path,
+            has_installer: self.has_installer,
+            add_to_path: self.add_to_path,
+            bin_name: &self.bin_name,
+            entry_points: &self.entry_points,
+            run: &self.run,
+        };
+
+        let mut bytes = SIGNATURE_CONTEXT.to_vec();
+        serde_json::to_writer(&mut bytes, &payload).context("Could not encode signed payload")?;
+        Ok(bytes)
+    }
+
+    /// Checks that the executable, entry point and working directory paths all stay within the package
+    pub fn check_paths(&self) -> anyhow::Result<()> {
+        let paths = self
+            .executable_path
+            .iter()
+            .chain(self.entry_points.iter().map(|entry| &entry.path))
+            .chain(&self.run.cwd);
+
+        for path in paths {
+            let within = Path::new(path)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if !within {
+                bail!(
+                    "Package {} has path {path:?}, which is not within the package",
+                    self.pkgname
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prefixed to signed payloads, so a signature can't be passed off as one over something else
+const SIGNATURE_CONTEXT: &[u8] = b"dcspkg-signature-v1\n";
+
+/// The fields of a package covered by its signature, in a fixed order.
+/// The run config's environment is a `BTreeMap`, so it is always encoded the same way too
+#[derive(Serialize)]
+struct SignedPayload<'a> {
+    pkgname: &'a str,
+    version: &'a Version,
+    sha256: &'a str,
+    executable_path: &'a Option<String>,
+    has_installer: bool,
+    add_to_path: bool,
+    bin_name: &'a Option<String>,
+    entry_points: &'a [EntryPoint],
+    run: &'a RunConfig,
+}
+
+fn unversioned() -> Version {
+    Version::new(0, 0, 0)
+}
+
+/// An entry in the local package registry. Contains the package's metadata,
+/// along with a manifest of everything that was installed for it.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct InstalledPackage {
+    #[serde(flatten)]
+    pub package: Package,
+    /// Registries written by older versions of dcspkg have no manifest
+    #[serde(default)]
+    pub manifest: Manifest,
+    /// The name the package's executable was added to path as, if chosen when installing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
 }
 
 const DATA_ENDPOINT: &str = "/pkgdata";
 const FILE_ENDPOINT: &str = "/download";
 const LIST_ENDPOINT: &str = "/list";
+const VERSIONS_ENDPOINT: &str = "/versions";
+const SEARCH_ENDPOINT: &str = "/search";
+
+/// The header the server uses to give the total number of packages when listing them a page at a time
+pub const TOTAL_COUNT_HEADER: &str = "X-Total-Count";
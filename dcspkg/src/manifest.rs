@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// A record of everything that installing a package wrote to disk.
+#[derive(Deserialize, Default, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// The files unpacked from the package archive
+    pub files: Vec<ManifestFile>,
+    /// The symlinks created in the bin directory
+    pub links: Vec<PathBuf>,
+}
+
+/// A single file unpacked from a package archive.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ManifestFile {
+    /// The path of the file, relative to the package's install directory
+    pub path: PathBuf,
+    /// The size of the file in bytes
+    pub size: u64,
+    /// The unix permissions of the file
+    pub mode: u32,
+    /// The hex encoded SHA-256 hash of the file's contents
+    pub sha256: String,
+}
+
+/// Hashes the file at `path`, returning the hex encoded SHA-256 digest
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Re-reads the size, mode and hash of each of `files` from disk, relative to `dir`,
+/// leaving out any that are no longer there. Used after an install script has run,
+/// as it may have changed or removed what was unpacked
+pub fn refresh_files(dir: &Path, files: Vec<ManifestFile>) -> std::io::Result<Vec<ManifestFile>> {
+    let mut refreshed = vec![];
+    for file in files {
+        let path = dir.join(&file.path);
+        let metadata = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        refreshed.push(ManifestFile {
+            sha256: hash_file(&path)?,
+            size: metadata.len(),
+            mode: metadata.permissions().mode() & 0o7777,
+            path: file.path,
+        });
+    }
+    Ok(refreshed)
+}
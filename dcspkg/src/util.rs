@@ -1,8 +1,14 @@
 use anyhow::Context;
-use std::path::Path;
+use fs2::FileExt;
+use indicatif::HumanBytes;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use tabular::{Row, Table};
+use tempfile::NamedTempFile;
 
-use crate::Package;
+use crate::{InstalledPackage, Package, PackageInfo, Upgrade};
 
 ///helper to print a list of packages as a nice table
 pub fn print_package_list(list: &[Package], raw: bool) {
@@ -13,10 +19,11 @@ pub fn print_package_list(list: &[Package], raw: bool) {
             println!("Package list is empty!");
             return;
         }
-        let mut table = Table::new("{:<}  {:<}  {:<}").with_row(
+        let mut table = Table::new("{:<}  {:<}  {:<}  {:<}").with_row(
             Row::new()
                 .with_cell("Game/App Name")
                 .with_cell("Package Shortname")
+                .with_cell("Version")
                 .with_cell("Description"),
         );
         for pkg in list {
@@ -24,6 +31,7 @@ pub fn print_package_list(list: &[Package], raw: bool) {
                 Row::new()
                     .with_cell(&pkg.fullname)
                     .with_cell(&pkg.pkgname)
+                    .with_cell(&pkg.version)
                     .with_cell(pkg.description.as_deref().unwrap_or("-")),
             );
         }
@@ -32,11 +40,228 @@ pub fn print_package_list(list: &[Package], raw: bool) {
     }
 }
 
+/// Helper to print a list of available upgrades as a nice table
+pub fn print_upgrade_list(list: &[Upgrade]) {
+    if list.is_empty() {
+        println!("All packages are up to date!");
+        return;
+    }
+    let mut table = Table::new("{:<}  {:<}  {:<}").with_row(
+        Row::new()
+            .with_cell("Package Shortname")
+            .with_cell("Installed")
+            .with_cell("Available"),
+    );
+    for upgrade in list {
+        //a package can be republished without changing version
+        let available = if upgrade.installed.version == upgrade.available.version {
+            format!("{} (republished)", upgrade.available.version)
+        } else {
+            upgrade.available.version.to_string()
+        };
+        table.add_row(
+            Row::new()
+                .with_cell(&upgrade.installed.pkgname)
+                .with_cell(&upgrade.installed.version)
+                .with_cell(available),
+        );
+    }
+
+    println!("{table}");
+}
+
+/// Helper to print the details of a package on the server and installed locally, side by side
+pub fn print_package_info(info: &PackageInfo, raw: bool) {
+    if raw {
+        println!("{}", serde_json::to_string(info).unwrap());
+        return;
+    }
+
+    let remote = info.remote.as_ref();
+    let installed = info.installed.as_ref();
+    let local = installed.map(|installed| &installed.entry.package);
+
+    let mut table = Table::new("{:<}  {:<}  {:<}").with_row(
+        Row::new()
+            .with_cell("")
+            .with_cell("Server")
+            .with_cell("Installed"),
+    );
+
+    //a row showing the same field of both packages, or "-" if there isn't one
+    let mut add_row = |name: &str, field: &dyn Fn(&Package) -> String| {
+        table.add_row(
+            Row::new()
+                .with_cell(name)
+                .with_cell(remote.map_or("-".to_string(), field))
+                .with_cell(local.map_or("-".to_string(), field)),
+        );
+    };
+    let or_dash = |field: &Option<String>| field.clone().unwrap_or_else(|| "-".to_string());
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+
+    add_row("Package Shortname", &|pkg| pkg.pkgname.clone());
+    add_row("Game/App Name", &|pkg| pkg.fullname.clone());
+    add_row("Version", &|pkg| pkg.version.to_string());
+    add_row("Description", &|pkg| or_dash(&pkg.description));
+    add_row("Image URL", &|pkg| or_dash(&pkg.image_url));
+    add_row("Executable", &|pkg| or_dash(&pkg.executable_path));
+    add_row("Entry Points", &|pkg| {
+        let names: Vec<&str> = pkg.entry_points.iter().map(|e| e.name.as_str()).collect();
+        if names.is_empty() {
+            "-".to_string()
+        } else {
+            names.join(", ")
+        }
+    });
+    add_row("Tags", &|pkg| {
+        if pkg.tags.is_empty() {
+            "-".to_string()
+        } else {
+            pkg.tags.join(", ")
+        }
+    });
+    add_row("Adds to Path", &|pkg| yes_no(pkg.add_to_path));
+    add_row("Has Installer", &|pkg| yes_no(pkg.has_installer));
+    add_row("Signed", &|pkg| yes_no(pkg.signature.is_some()));
+    add_row("Download Size", &|pkg| {
+        pkg.size
+            .map_or("-".to_string(), |size| HumanBytes(size).to_string())
+    });
+    add_row("SHA-256", &|pkg| or_dash(&pkg.sha256));
+
+    //these only make sense for an installed package
+    if let Some(installed) = installed {
+        let links: Vec<String> = installed
+            .entry
+            .manifest
+            .links
+            .iter()
+            .map(|link| link.display().to_string())
+            .collect();
+        let rows = [
+            ("Location", installed.location.display().to_string()),
+            ("Size", HumanBytes(installed.size).to_string()),
+            ("On Path As", links.join(", ")),
+        ];
+        for (name, value) in rows {
+            if !value.is_empty() {
+                table.add_row(Row::new().with_cell(name).with_cell("").with_cell(value));
+            }
+        }
+    }
+
+    println!("{table}");
+
+    match (remote, local, &info.server_error) {
+        (Some(remote), Some(local), _) if remote.version > local.version => {
+            println!("A newer version is available, use `dcspkg upgrade` to install it")
+        }
+        (None, _, Some(e)) => println!("{e}, run with -v for details"),
+        (None, Some(_), None) => println!("This package is not available from the server"),
+        _ => (),
+    }
+}
+
 /// Helper to get the list of packages from the json file on disk
-pub fn list_installed_packages(path: &Path) -> anyhow::Result<Vec<Package>> {
-    std::fs::File::open(path)
-        .context("Could not open registry file")
-        .and_then(|reader| {
-            serde_json::from_reader(reader).context("Could not parse JSON from registry")
+pub fn list_installed_packages(path: &Path) -> anyhow::Result<Vec<InstalledPackage>> {
+    let reader = std::fs::File::open(path).context("Could not open registry file")?;
+
+    //older versions could leave trailing garbage after the json when rewriting the registry,
+    //so only read the first value
+    let mut packages: Vec<InstalledPackage> =
+        serde_json::Deserializer::from_reader(BufReader::new(reader))
+            .into_iter()
+            .next()
+            .context("Registry file is empty")?
+            .context("Could not parse JSON from registry")?;
+
+    //older versions could also add the same package more than once, so keep the last one added
+    let mut seen = HashSet::new();
+    packages.reverse();
+    packages.retain(|installed| seen.insert(installed.package.pkgname.clone()));
+    packages.reverse();
+
+    Ok(packages)
+}
+
+/// Helper to make a change to the json registry file on disk.
+/// An exclusive lock is held on the registry while `f` runs, so concurrent dcspkg processes
+/// can't lose each other's changes. The new registry is written to a temporary file which
+/// is renamed over the old one, so it is never seen half written.
+/// Nothing is written if `f` returns an error.
+pub fn update_installed_packages<T, F>(path: &Path, f: F) -> anyhow::Result<T>
+where
+    F: FnOnce(&mut Vec<InstalledPackage>) -> anyhow::Result<T>,
+{
+    let mut registry = LockedRegistry::open(path)?;
+    let result = f(&mut registry.packages)?;
+    registry.save()?;
+    Ok(result)
+}
+
+/// The json registry file on disk, read while holding an exclusive lock on it.
+/// No other dcspkg process can change the registry until this is dropped,
+/// so decisions made from `packages` stay valid for as long as it is held.
+pub(crate) struct LockedRegistry {
+    path: PathBuf,
+    _lock: File,
+    pub(crate) packages: Vec<InstalledPackage>,
+}
+
+impl LockedRegistry {
+    /// Locks and reads the registry, which is empty if it doesn't exist yet
+    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+        let lock = lock_registry(path)?;
+
+        let packages = if path.exists() {
+            list_installed_packages(path)?
+        } else {
+            vec![]
+        };
+
+        Ok(LockedRegistry {
+            path: path.to_owned(),
+            _lock: lock,
+            packages,
         })
+    }
+
+    /// Writes `packages` back to the registry
+    pub(crate) fn save(&self) -> anyhow::Result<()> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut file =
+            NamedTempFile::new_in(dir).context("Could not create temporary registry file")?;
+        serde_json::to_writer(&mut file, &self.packages).context("Could not serialize registry")?;
+        file.as_file()
+            .sync_all()
+            .context("Could not write temporary registry file")?;
+        file.persist(&self.path)
+            .context("Could not write registry back to file")?;
+
+        Ok(())
+    }
+}
+
+/// Takes an exclusive lock on the registry, which is released when the returned file is dropped.
+/// The registry is replaced on every write, so a separate lock file is used.
+fn lock_registry(path: &Path) -> anyhow::Result<File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .context("Could not open registry lock file")?;
+
+    log::debug!("Waiting for lock on {lock_path:?}");
+    file.lock_exclusive()
+        .context("Could not lock registry file")?;
+
+    Ok(file)
 }
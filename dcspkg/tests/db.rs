@@ -0,0 +1,483 @@
+//! Tests for the package database. These need the `db` feature, which the server turns on
+//! when testing the whole workspace, otherwise run them with `cargo test -p dcspkg --features db`
+#![cfg(feature = "db")]
+
+use dcspkg::db::{migrate, schema_version, ListOrder, PackageStore, SCHEMA_VERSION};
+use dcspkg::{EntryPoint, Package, RunConfig};
+use semver::Version;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+
+/// An in-memory database only lives as long as its connection, so the pool must keep exactly one open
+async fn memory_pool() -> SqlitePool {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+async fn store() -> PackageStore {
+    PackageStore::from_pool(memory_pool().await).await.unwrap()
+}
+
+fn package(name: &str, version: &str) -> Package {
+    Package {
+        pkgname: name.to_owned(),
+        version: version.parse().unwrap(),
+        fullname: format!("The {name} package"),
+        description: Some(format!("Does {name} things")),
+        image_url: None,
+        executable_path: Some(format!("bin/{name}")),
+        crc: 1234,
+        sha256: Some("ab".repeat(32)),
+        signature: None,
+        has_installer: false,
+        add_to_path: true,
+        bin_name: None,
+        entry_points: vec![],
+        run: RunConfig::default(),
+        tags: vec![],
+        size: Some(4096),
+        updated: Some(1_700_000_000),
+        yanked: false,
+    }
+}
+
+fn version(v: &str) -> Version {
+    v.parse().unwrap()
+}
+
+#[tokio::test]
+async fn migrations_bring_schema_up_to_date() {
+    let pool = memory_pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+
+    assert_eq!(schema_version(&mut conn).await.unwrap(), 0);
+    migrate(&mut conn).await.unwrap();
+    assert_eq!(schema_version(&mut conn).await.unwrap(), SCHEMA_VERSION);
+
+    //running them again does nothing
+    migrate(&mut conn).await.unwrap();
+    assert_eq!(schema_version(&mut conn).await.unwrap(), SCHEMA_VERSION);
+}
+
+/// The packages table as created by `scripts/initdb.sh`, before migrations were tracked
+const BASELINE_SCHEMA: &str = "CREATE TABLE packages(
+    pkgname STRING PRIMARY KEY NOT NULL,
+    fullname STRING NOT NULL,
+    description STRING,
+    image_url STRING,
+    executable_path STRING,
+    crc INTEGER NOT NULL,
+    has_installer INTEGER NOT NULL,
+    add_to_path INTEGER NOT NULL)";
+
+#[tokio::test]
+async fn baseline_database_is_migrated() {
+    let pool = memory_pool().await;
+    sqlx::query(BASELINE_SCHEMA).execute(&pool).await.unwrap();
+    sqlx::query(
+        "INSERT INTO packages VALUES \
+         ('hello', 'Hello World', 'Says hello', NULL, 'bin/hello', 1234, 0, 1), \
+         ('game', 'A Game', NULL, 'https://example.com/game.png', 'game', 5678, 1, 0)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let store = PackageStore::from_pool(pool.clone()).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    assert_eq!(schema_version(&mut conn).await.unwrap(), SCHEMA_VERSION);
+    drop(conn);
+
+    //existing packages become unversioned, and keep everything they had
+    let hello = store
+        .get("hello", &version("0.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(hello.fullname, "Hello World");
+    assert_eq!(hello.description.as_deref(), Some("Says hello"));
+    assert_eq!(hello.executable_path.as_deref(), Some("bin/hello"));
+    assert_eq!(hello.crc, 1234);
+    assert!(hello.add_to_path && !hello.has_installer);
+    assert_eq!(hello.sha256, None);
+    assert!(hello.entry_points.is_empty() && hello.tags.is_empty() && !hello.yanked);
+
+    let names: Vec<String> = store
+        .list(None, ListOrder::Name, None, 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|pkg| pkg.pkgname)
+        .collect();
+    assert_eq!(names, vec!["game", "hello"]);
+    assert_eq!(store.search("hello").await.unwrap().len(), 1);
+
+    //and new versions can be added alongside them
+    store.insert(&package("hello", "1.0.0")).await.unwrap();
+    let latest = store.latest("hello").await.unwrap().unwrap();
+    assert_eq!(latest.version, version("1.0.0"));
+    assert_eq!(store.count(None).await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn future_schema_is_refused() {
+    let pool = memory_pool().await;
+    sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert!(PackageStore::from_pool(pool).await.is_err());
+}
+
+#[tokio::test]
+async fn insert_and_get_round_trips() {
+    let store = store().await;
+    let mut pkg = package("hello", "1.2.3");
+    pkg.bin_name = Some("hi".to_owned());
+    pkg.entry_points = vec![EntryPoint {
+        name: "server".to_owned(),
+        path: "bin/server".to_owned(),
+        add_to_path: true,
+    }];
+    pkg.run
+        .env
+        .insert("FOO".to_owned(), "${PKGDIR}/foo".to_owned());
+    pkg.run.launcher = true;
+    pkg.tags = vec!["games".to_owned(), "tools".to_owned()];
+    store.insert(&pkg).await.unwrap();
+
+    let got = store.get("hello", &version("1.2.3")).await.unwrap();
+    assert_eq!(got, Some(pkg));
+    assert_eq!(store.get("hello", &version("1.0.0")).await.unwrap(), None);
+    assert_eq!(store.get("nope", &version("1.2.3")).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn duplicate_version_is_rejected() {
+    let store = store().await;
+    store.insert(&package("hello", "1.0.0")).await.unwrap();
+
+    assert!(store.insert(&package("hello", "1.0.0")).await.is_err());
+}
+
+#[tokio::test]
+async fn versions_are_ordered_by_semver() {
+    let store = store().await;
+    for v in ["1.10.0", "1.2.0", "0.9.0"] {
+        store.insert(&package("hello", v)).await.unwrap();
+    }
+
+    let versions: Vec<Version> = store
+        .versions("hello")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|pkg| pkg.version)
+        .collect();
+    assert_eq!(
+        versions,
+        vec![version("0.9.0"), version("1.2.0"), version("1.10.0")]
+    );
+
+    let latest = store.latest("hello").await.unwrap().unwrap();
+    assert_eq!(latest.version, version("1.10.0"));
+    assert_eq!(store.latest("nope").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn list_gives_latest_of_each_by_name() {
+    let store = store().await;
+    let mut old = package("zed", "1.0.0");
+    old.tags = vec!["editors".to_owned()];
+    store.insert(&old).await.unwrap();
+    let mut new = package("zed", "2.0.0");
+    new.tags = vec!["editors".to_owned()];
+    store.insert(&new).await.unwrap();
+    store.insert(&package("gcc", "12.0.0")).await.unwrap();
+
+    let all = store.list(None, ListOrder::Name, None, 0).await.unwrap();
+    let names: Vec<(&str, String)> = all
+        .iter()
+        .map(|pkg| (pkg.pkgname.as_str(), pkg.version.to_string()))
+        .collect();
+    assert_eq!(
+        names,
+        vec![("gcc", "12.0.0".to_owned()), ("zed", "2.0.0".to_owned())]
+    );
+    assert_eq!(store.count(None).await.unwrap(), 2);
+
+    let editors = store
+        .list(Some("Editors"), ListOrder::Name, None, 0)
+        .await
+        .unwrap();
+    assert_eq!(editors, vec![new]);
+    assert_eq!(store.count(Some("Editors")).await.unwrap(), 1);
+    assert!(store
+        .list(Some("games"), ListOrder::Name, None, 0)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(store.count(Some("games")).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn list_sorts_and_pages() {
+    let store = store().await;
+    for (name, size, updated) in [
+        ("a", Some(10), Some(300)),
+        ("b", None, None),
+        ("c", Some(30), Some(100)),
+        ("d", Some(20), Some(200)),
+    ] {
+        let mut pkg = package(name, "1.0.0");
+        pkg.size = size;
+        pkg.updated = updated;
+        store.insert(&pkg).await.unwrap();
+    }
+
+    let names = |packages: Vec<Package>| -> Vec<String> {
+        packages.into_iter().map(|pkg| pkg.pkgname).collect()
+    };
+    let list = |order, limit, offset| store.list(None, order, limit, offset);
+
+    assert_eq!(
+        names(list(ListOrder::Updated, None, 0).await.unwrap()),
+        ["a", "d", "c", "b"]
+    );
+    assert_eq!(
+        names(list(ListOrder::Size, None, 0).await.unwrap()),
+        ["c", "d", "a", "b"]
+    );
+    assert_eq!(
+        names(list(ListOrder::Name, Some(2), 1).await.unwrap()),
+        ["b", "c"]
+    );
+    assert!(list(ListOrder::Name, Some(2), 4).await.unwrap().is_empty());
+    assert_eq!(store.count(None).await.unwrap(), 4);
+}
+
+#[tokio::test]
+async fn existing_packages_are_marked_latest_on_open() {
+    //as if added before the latest column existed
+    let pool = memory_pool().await;
+    migrate(&mut pool.acquire().await.unwrap()).await.unwrap();
+    for version in ["1.10.0", "1.2.0"] {
+        sqlx::query(
+            "INSERT INTO packages (pkgname, version, fullname, crc, has_installer, add_to_path) \
+             VALUES ('hello', ?, 'Hello', 0, 0, 0)",
+        )
+        .bind(version)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let store = PackageStore::from_pool(pool).await.unwrap();
+    let listed = store.list(None, ListOrder::Name, None, 0).await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].version, version("1.10.0"));
+}
+
+#[tokio::test]
+async fn tags_are_replaced_by_each_version() {
+    let store = store().await;
+    let mut pkg = package("hello", "1.0.0");
+    pkg.tags = vec!["tools".to_owned(), "games".to_owned()];
+    store.insert(&pkg).await.unwrap();
+    assert_eq!(store.tags("hello").await.unwrap(), vec!["games", "tools"]);
+
+    let mut pkg = package("hello", "2.0.0");
+    pkg.tags = vec!["utils".to_owned()];
+    store.insert(&pkg).await.unwrap();
+    assert_eq!(store.tags("hello").await.unwrap(), vec!["utils"]);
+
+    //tags belong to the package, so every version has them
+    let old = store
+        .get("hello", &version("1.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(old.tags, vec!["utils"]);
+}
+
+#[tokio::test]
+async fn search_ranks_name_matches_first() {
+    let store = store().await;
+    let mut compiler = package("gcc", "1.0.0");
+    compiler.description = Some("A compiler".to_owned());
+    store.insert(&compiler).await.unwrap();
+    let mut other = package("clang", "1.0.0");
+    other.description = Some("Not gcc, but compiles C".to_owned());
+    store.insert(&other).await.unwrap();
+    store.insert(&package("gcc", "2.0.0")).await.unwrap();
+
+    let found: Vec<String> = store
+        .search("gcc")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|pkg| format!("{}-{}", pkg.pkgname, pkg.version))
+        .collect();
+    assert_eq!(found, vec!["gcc-2.0.0", "clang-1.0.0"]);
+
+    assert_eq!(store.search("compiler").await.unwrap().len(), 1);
+    assert!(store.search("\"nothing\" OR").await.unwrap().is_empty());
+    assert!(store.search("   ").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn search_index_only_changes_with_searched_columns() {
+    let pool = memory_pool().await;
+    let store = PackageStore::from_pool(pool.clone()).await.unwrap();
+    store.insert(&package("gcc", "1.0.0")).await.unwrap();
+    store.insert(&package("gcc", "2.0.0")).await.unwrap();
+
+    let rows = || async {
+        sqlx::query_as::<_, (i64, String)>("SELECT rowid, version FROM packages_fts ORDER BY rowid")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+    };
+    let before = rows().await;
+
+    //yanking changes which version is the latest, but nothing that is searched
+    store
+        .set_yanked("gcc", &version("2.0.0"), true)
+        .await
+        .unwrap();
+    assert_eq!(rows().await, before);
+
+    let found = store.search("gcc").await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].version, version("1.0.0"));
+}
+
+#[tokio::test]
+async fn update_replaces_metadata() {
+    let store = store().await;
+    store.insert(&package("hello", "1.0.0")).await.unwrap();
+
+    let mut pkg = package("hello", "1.0.0");
+    pkg.fullname = "Hello World".to_owned();
+    pkg.description = None;
+    pkg.run.args = vec!["--quiet".to_owned()];
+    pkg.tags = vec!["demo".to_owned()];
+    assert!(store.update(&pkg).await.unwrap());
+    assert_eq!(
+        store.get("hello", &version("1.0.0")).await.unwrap(),
+        Some(pkg)
+    );
+
+    //search follows the update
+    assert_eq!(store.search("world").await.unwrap().len(), 1);
+
+    let missing = package("hello", "9.9.9");
+    assert!(!store.update(&missing).await.unwrap());
+    assert_eq!(store.versions("hello").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn delete_removes_one_version() {
+    let store = store().await;
+    let mut pkg = package("hello", "1.0.0");
+    pkg.tags = vec!["demo".to_owned()];
+    store.insert(&pkg).await.unwrap();
+    pkg.version = version("2.0.0");
+    store.insert(&pkg).await.unwrap();
+
+    assert!(store.delete("hello", &version("2.0.0")).await.unwrap());
+    assert!(!store.delete("hello", &version("2.0.0")).await.unwrap());
+    let latest = store.latest("hello").await.unwrap().unwrap();
+    assert_eq!(latest.version, version("1.0.0"));
+    assert_eq!(latest.tags, vec!["demo"]);
+
+    //tags go with the last version
+    assert!(store.delete("hello", &version("1.0.0")).await.unwrap());
+    assert_eq!(store.latest("hello").await.unwrap(), None);
+    assert!(store.tags("hello").await.unwrap().is_empty());
+    assert!(store.search("hello").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn yanked_versions_are_hidden() {
+    let store = store().await;
+    store.insert(&package("hello", "1.0.0")).await.unwrap();
+    store.insert(&package("hello", "2.0.0")).await.unwrap();
+
+    assert!(store
+        .set_yanked("hello", &version("2.0.0"), true)
+        .await
+        .unwrap());
+    let latest = store.latest("hello").await.unwrap().unwrap();
+    assert_eq!(latest.version, version("1.0.0"));
+    let listed = store.list(None, ListOrder::Name, None, 0).await.unwrap();
+    assert_eq!(listed, vec![latest]);
+
+    //but can still be got by version
+    let yanked = store
+        .get("hello", &version("2.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(yanked.yanked);
+    assert_eq!(store.versions("hello").await.unwrap().len(), 2);
+
+    //a package with only yanked versions isn't listed at all
+    store
+        .set_yanked("hello", &version("1.0.0"), true)
+        .await
+        .unwrap();
+    assert_eq!(store.latest("hello").await.unwrap(), None);
+    assert!(store
+        .list(None, ListOrder::Name, None, 0)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(store.count(None).await.unwrap(), 0);
+    assert!(store.search("hello").await.unwrap().is_empty());
+
+    store
+        .set_yanked("hello", &version("2.0.0"), false)
+        .await
+        .unwrap();
+    let latest = store.latest("hello").await.unwrap().unwrap();
+    assert_eq!(latest.version, version("2.0.0"));
+
+    assert!(!store
+        .set_yanked("hello", &version("9.9.9"), true)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn transactions_only_apply_when_committed() {
+    let store = store().await;
+    store.insert(&package("hello", "1.0.0")).await.unwrap();
+
+    let mut transaction = store.begin().await.unwrap();
+    transaction
+        .insert(&package("other", "1.0.0"))
+        .await
+        .unwrap();
+    assert!(transaction
+        .delete("hello", &version("1.0.0"))
+        .await
+        .unwrap());
+    drop(transaction);
+
+    assert!(store.latest("other").await.unwrap().is_none());
+    assert!(store.latest("hello").await.unwrap().is_some());
+
+    let mut transaction = store.begin().await.unwrap();
+    transaction
+        .insert(&package("other", "1.0.0"))
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+    assert!(store.latest("other").await.unwrap().is_some());
+}